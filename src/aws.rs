@@ -1,2 +0,0 @@
-/* This is a stub module. Currently you can signal the AWS ASG by using run_on_failure parameter.
-*/
\ No newline at end of file
@@ -0,0 +1,303 @@
+/* Publishes the container stats to CloudWatch with PutMetricData every `[aws.cloudwatch] interval`.
+    Per container: Unhealthy (0/1), ConsecutiveFailures and Restarts (made since the previous publish,
+    so the Sum statistic adds up to the total),
+    per node: UnhealthyContainers and NodeHealthy (0 when a critical container fails, same as /node-health).
+*/
+use super::Aws;
+use config::{AwsConfig, Dimension};
+use docker_checker::Health;
+use metrics::{ContainerSnapshot, METRICS};
+use status;
+use std::collections::HashMap;
+use std::thread;
+
+const CLOUDWATCH_API_VERSION: &str = "2010-08-01";
+// PutMetricData limit for older API versions and most of the local stand-ins
+const MAX_METRICS_PER_REQUEST: usize = 20;
+const ASG_NAME_TAG: &str = "aws:autoscaling:groupName";
+
+#[derive(Debug, PartialEq)]
+pub struct MetricDatum {
+    pub name: &'static str,
+    pub value: f64,
+    pub dimensions: Vec<(&'static str, String)>,
+}
+
+impl Dimension {
+    fn name(self) -> &'static str {
+        match self {
+            Dimension::InstanceId => "InstanceId",
+            Dimension::AsgName => "AutoScalingGroupName",
+            Dimension::ContainerName => "ContainerName",
+        }
+    }
+}
+
+fn flag(value: bool) -> f64 {
+    if value {
+        1.0
+    } else {
+        0.0
+    }
+}
+
+/// Builds the metrics from the container stats. `node_dimensions` are added to every metric,
+/// container metrics get the container name as well when `with_container` is set.
+/// `published_restarts` are the restarts by container id at the previous publish.
+pub fn metric_data(
+    containers: &[ContainerSnapshot],
+    published_restarts: &HashMap<String, u32>,
+    node_dimensions: &[(&'static str, String)],
+    with_container: bool,
+) -> Vec<MetricDatum> {
+    let mut data = Vec::new();
+    for container in containers {
+        let mut dimensions = node_dimensions.to_vec();
+        if with_container {
+            dimensions.push((
+                Dimension::ContainerName.name(),
+                container.name.trim_start_matches('/').to_string(),
+            ));
+        }
+        let unhealthy = container.health == Some(Health::Unhealthy);
+        data.push(MetricDatum {
+            name: "Unhealthy",
+            value: flag(unhealthy),
+            dimensions: dimensions.clone(),
+        });
+        data.push(MetricDatum {
+            name: "ConsecutiveFailures",
+            value: f64::from(container.consecutive_failures),
+            dimensions: dimensions.clone(),
+        });
+        let published = published_restarts.get(&container.id).cloned().unwrap_or(0);
+        data.push(MetricDatum {
+            name: "Restarts",
+            value: f64::from(container.restarts.saturating_sub(published)),
+            dimensions,
+        });
+    }
+    let unhealthy = containers
+        .iter()
+        .filter(|container| container.health == Some(Health::Unhealthy))
+        .count();
+    data.push(MetricDatum {
+        name: "UnhealthyContainers",
+        value: unhealthy as f64,
+        dimensions: node_dimensions.to_vec(),
+    });
+    data.push(MetricDatum {
+        name: "NodeHealthy",
+        value: flag(!containers.iter().any(status::is_failing)),
+        dimensions: node_dimensions.to_vec(),
+    });
+    data
+}
+
+/// Query API parameters of a PutMetricData call
+pub fn put_metric_data_params(namespace: &str, data: &[MetricDatum]) -> Vec<(String, String)> {
+    let mut params = vec![
+        ("Action".to_string(), "PutMetricData".to_string()),
+        ("Namespace".to_string(), namespace.to_string()),
+        ("Version".to_string(), CLOUDWATCH_API_VERSION.to_string()),
+    ];
+    for (idx, datum) in data.iter().enumerate() {
+        let member = format!("MetricData.member.{}", idx + 1);
+        params.push((format!("{}.MetricName", member), datum.name.to_string()));
+        params.push((format!("{}.Value", member), datum.value.to_string()));
+        params.push((format!("{}.Unit", member), "Count".to_string()));
+        for (dim_idx, (name, value)) in datum.dimensions.iter().enumerate() {
+            let dimension = format!("{}.Dimensions.member.{}", member, dim_idx + 1);
+            params.push((format!("{}.Name", dimension), name.to_string()));
+            params.push((format!("{}.Value", dimension), value.clone()));
+        }
+    }
+    params
+}
+
+impl Aws {
+    /// Sends the current container stats to CloudWatch, `published_restarts` are updated when it succeeds
+    pub fn publish_metrics(
+        &self,
+        containers: &[ContainerSnapshot],
+        published_restarts: &mut HashMap<String, u32>,
+    ) -> Result<(), String> {
+        let config = &self.config.cloudwatch;
+        let session = self.session()?;
+        let mut node_dimensions = Vec::new();
+        if config.dimensions.contains(&Dimension::InstanceId) {
+            node_dimensions.push((Dimension::InstanceId.name(), session.instance_id.clone()));
+        }
+        if config.dimensions.contains(&Dimension::AsgName) {
+            let asg_name = match config.asg_name {
+                Some(ref name) => Ok(name.clone()),
+                None => session.imds.tag(ASG_NAME_TAG),
+            };
+            match asg_name {
+                Ok(name) => node_dimensions.push((Dimension::AsgName.name(), name)),
+                Err(e) => debug!(
+                    "Cannot get the Auto Scaling group name, the dimension is skipped: {}",
+                    e
+                ),
+            }
+        }
+        let data = metric_data(
+            containers,
+            published_restarts,
+            &node_dimensions,
+            config.dimensions.contains(&Dimension::ContainerName),
+        );
+
+        let endpoint = match config.endpoint {
+            Some(ref endpoint) => endpoint.clone(),
+            None => format!("https://monitoring.{}.amazonaws.com/", session.region),
+        };
+        for chunk in data.chunks(MAX_METRICS_PER_REQUEST) {
+            let params = put_metric_data_params(&config.namespace, chunk);
+            let params = params
+                .iter()
+                .map(|(name, value)| (name.as_str(), value.as_str()))
+                .collect::<Vec<_>>();
+            self.call(&endpoint, "monitoring", &session.region, &session.credentials, &params)?;
+        }
+        *published_restarts = containers
+            .iter()
+            .map(|container| (container.id.clone(), container.restarts))
+            .collect();
+        Ok(())
+    }
+}
+
+/// Publishes the metrics in a separate thread every `[aws.cloudwatch] interval`
+pub fn spawn(config: AwsConfig) -> Result<(), String> {
+    let aws = Aws::new(&config)?;
+    info!(
+        "Publishing metrics to CloudWatch namespace {} every {:?}",
+        config.cloudwatch.namespace, config.cloudwatch.interval
+    );
+    thread::spawn(move || {
+        let mut published_restarts = HashMap::new();
+        loop {
+            thread::sleep(config.cloudwatch.interval);
+            // restarts that failed to be published go with the next publish
+            if let Err(e) = aws.publish_metrics(&METRICS.containers(), &mut published_restarts) {
+                error!("Cannot publish metrics to CloudWatch: {}", e);
+            }
+        }
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::mock_server;
+    use super::*;
+    use config::{AwsAsgConfig, CloudWatchConfig};
+    use std::time::Duration;
+
+    fn containers() -> Vec<ContainerSnapshot> {
+        vec![
+            ContainerSnapshot {
+                id: "dfdb8ee577c1".to_string(),
+                name: "/web".to_string(),
+                health: Some(Health::Healthy),
+                restarts: 2,
+                ..Default::default()
+            },
+            ContainerSnapshot {
+                id: "ce94baa47eed".to_string(),
+                name: "/db".to_string(),
+                health: Some(Health::Unhealthy),
+                consecutive_failures: 4,
+                critical: true,
+                ..Default::default()
+            },
+        ]
+    }
+
+    #[test]
+    fn metric_data_test() {
+        let node = vec![("InstanceId", "i-1".to_string())];
+        let data = metric_data(&containers(), &HashMap::new(), &node, true);
+        assert_eq!(data.len(), 8);
+        assert_eq!(
+            data[2],
+            MetricDatum {
+                name: "Restarts",
+                value: 2.0,
+                dimensions: vec![("InstanceId", "i-1".to_string()), ("ContainerName", "web".to_string())],
+            }
+        );
+        assert_eq!(data[3].value, 1.0);
+        assert_eq!(data[4].value, 4.0);
+        assert_eq!(data[6].name, "UnhealthyContainers");
+        assert_eq!(data[6].value, 1.0);
+        assert_eq!(
+            data[7],
+            MetricDatum {
+                name: "NodeHealthy",
+                value: 0.0,
+                dimensions: node.clone(),
+            }
+        );
+
+        // only the restarts made since the previous publish
+        let mut published = HashMap::new();
+        published.insert("dfdb8ee577c1".to_string(), 1);
+        let data = metric_data(&containers(), &published, &node, true);
+        assert_eq!(data[2].value, 1.0);
+        assert_eq!(data[5].value, 0.0);
+    }
+
+    #[test]
+    fn put_metric_data_params_test() {
+        let data = metric_data(&containers()[..1], &HashMap::new(), &[], false);
+        let params = put_metric_data_params("DockerCheck", &data[..1]);
+        assert_eq!(
+            params,
+            vec![
+                ("Action".to_string(), "PutMetricData".to_string()),
+                ("Namespace".to_string(), "DockerCheck".to_string()),
+                ("Version".to_string(), "2010-08-01".to_string()),
+                ("MetricData.member.1.MetricName".to_string(), "Unhealthy".to_string()),
+                ("MetricData.member.1.Value".to_string(), "0".to_string()),
+                ("MetricData.member.1.Unit".to_string(), "Count".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn publish_metrics_test() {
+        let (url, receiver) = mock_server();
+        let config = AwsConfig {
+            enabled: true,
+            region: None,
+            imds_url: url.clone(),
+            timeout: Duration::from_secs(5),
+            asg: AwsAsgConfig {
+                healthcheck: false,
+                endpoint: None,
+                respect_grace_period: true,
+            },
+            cloudwatch: CloudWatchConfig {
+                enabled: true,
+                endpoint: Some(format!("{}/monitoring", url)),
+                ..Default::default()
+            },
+        };
+        let mut published = HashMap::new();
+        Aws::new(&config)
+            .unwrap()
+            .publish_metrics(&containers(), &mut published)
+            .unwrap();
+        assert_eq!(published.get("dfdb8ee577c1"), Some(&2));
+
+        let call = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(call.url, "/monitoring");
+        assert!(call.authorization.contains("/eu-west-1/monitoring/aws4_request"));
+        assert!(call.body.starts_with("Action=PutMetricData&Namespace=DockerCheck&"));
+        assert!(call.body.contains("Dimensions.member.1.Name=InstanceId&"));
+        assert!(call.body.contains("Dimensions.member.2.Value=web-asg&"));
+        assert!(call.body.contains("Dimensions.member.3.Value=db&"));
+    }
+}
@@ -0,0 +1,90 @@
+/* Instance Metadata Service v2 client: instance id, region and instance role credentials.
+    Every session starts with a PUT for a token that is sent with the following requests.
+*/
+use super::sigv4::Credentials;
+use reqwest::Client;
+use serde_json;
+
+const TOKEN_PATH: &str = "/latest/api/token";
+const TOKEN_TTL_HEADER: &str = "X-aws-ec2-metadata-token-ttl-seconds";
+pub(crate) const TOKEN_HEADER: &str = "X-aws-ec2-metadata-token";
+// the token is only used for a couple of requests right after it's issued
+const TOKEN_TTL: &str = "60";
+const CREDENTIALS_PATH: &str = "/latest/meta-data/iam/security-credentials/";
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct RoleCredentials {
+    access_key_id: String,
+    secret_access_key: String,
+    token: Option<String>,
+}
+
+pub struct Imds<'a> {
+    client: &'a Client,
+    url: String,
+    token: String,
+}
+
+impl<'a> Imds<'a> {
+    /// Starts a session with IMDS at `url`, e.g. http://169.254.169.254
+    pub fn connect(client: &'a Client, url: &str) -> Result<Self, String> {
+        let url = url.trim_end_matches('/').to_string();
+        let mut response = client
+            .put(&format!("{}{}", url, TOKEN_PATH))
+            .header(TOKEN_TTL_HEADER, TOKEN_TTL)
+            .send()
+            .map_err(|e| format!("Cannot get IMDS token from {}: {}", url, e))?;
+        if !response.status().is_success() {
+            return Err(format!("Cannot get IMDS token from {}: {}", url, response.status()));
+        }
+        let token = response.text().map_err(|e| format!("Cannot read IMDS token: {}", e))?;
+        Ok(Self { client, url, token })
+    }
+
+    fn get(&self, path: &str) -> Result<String, String> {
+        let mut response = self
+            .client
+            .get(&format!("{}{}", self.url, path))
+            .header(TOKEN_HEADER, self.token.as_str())
+            .send()
+            .map_err(|e| format!("Cannot get {} from IMDS: {}", path, e))?;
+        if !response.status().is_success() {
+            return Err(format!("Cannot get {} from IMDS: {}", path, response.status()));
+        }
+        response
+            .text()
+            .map(|text| text.trim().to_string())
+            .map_err(|e| format!("Cannot read {} from IMDS: {}", path, e))
+    }
+
+    pub fn instance_id(&self) -> Result<String, String> {
+        self.get("/latest/meta-data/instance-id")
+    }
+
+    pub fn region(&self) -> Result<String, String> {
+        self.get("/latest/meta-data/placement/region")
+    }
+
+    /// Instance tag, only available when access to tags in instance metadata is allowed
+    pub fn tag(&self, name: &str) -> Result<String, String> {
+        self.get(&format!("/latest/meta-data/tags/instance/{}", name))
+    }
+
+    /// Temporary credentials of the first instance role
+    pub fn credentials(&self) -> Result<Credentials, String> {
+        let roles = self.get(CREDENTIALS_PATH)?;
+        let role = roles
+            .lines()
+            .next()
+            .ok_or_else(|| "Instance has no IAM role".to_string())?;
+        let document = self.get(&format!("{}{}", CREDENTIALS_PATH, role))?;
+        let credentials: RoleCredentials =
+            serde_json::from_str(&document).map_err(|e| format!("Cannot parse credentials of role {}: {}", role, e))?;
+        Ok(Credentials {
+            access_key_id: credentials.access_key_id,
+            secret_access_key: credentials.secret_access_key,
+            session_token: credentials.token,
+        })
+    }
+}
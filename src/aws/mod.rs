@@ -0,0 +1,264 @@
+/* Native AWS integration. When a container reaches hard_failures and `[aws.asg] healthcheck` is enabled,
+    the instance is marked Unhealthy in its Auto Scaling group so the group replaces it.
+    With `[aws.cloudwatch]` the container stats are published as CloudWatch metrics, see cloudwatch.rs.
+    Instance id, region and (unless set in the environment) credentials come from IMDSv2.
+    Both IMDS and the API endpoint can be overridden, e.g. to point them at a local mock.
+*/
+pub mod cloudwatch;
+pub mod imds;
+pub mod sigv4;
+
+use self::imds::Imds;
+use self::sigv4::Credentials;
+use chrono::Utc;
+use config::AwsConfig;
+use reqwest::{Client, Url};
+use std::env;
+
+const ASG_API_VERSION: &str = "2011-01-01";
+const FORM_CONTENT_TYPE: &str = "application/x-www-form-urlencoded; charset=utf-8";
+
+pub struct Aws {
+    client: Client,
+    config: AwsConfig,
+}
+
+// what every API call needs to know about the instance
+struct Session<'a> {
+    imds: Imds<'a>,
+    instance_id: String,
+    region: String,
+    credentials: Credentials,
+}
+
+fn credentials_from_env() -> Option<Credentials> {
+    match (env::var("AWS_ACCESS_KEY_ID"), env::var("AWS_SECRET_ACCESS_KEY")) {
+        (Ok(access_key_id), Ok(secret_access_key)) => Some(Credentials {
+            access_key_id,
+            secret_access_key,
+            session_token: env::var("AWS_SESSION_TOKEN").ok(),
+        }),
+        _ => None,
+    }
+}
+
+fn form_encode(params: &[(&str, &str)]) -> String {
+    params
+        .iter()
+        .map(|(name, value)| format!("{}={}", sigv4::uri_encode(name), sigv4::uri_encode(value)))
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+impl Aws {
+    pub fn new(config: &AwsConfig) -> Result<Self, String> {
+        let client = Client::builder()
+            .timeout(config.timeout)
+            .build()
+            .map_err(|e| format!("Cannot create HTTP client: {}", e))?;
+        Ok(Self {
+            client,
+            config: config.clone(),
+        })
+    }
+
+    fn session(&self) -> Result<Session, String> {
+        let imds = Imds::connect(&self.client, &self.config.imds_url)?;
+        let instance_id = imds.instance_id()?;
+        let region = match self.config.region {
+            Some(ref region) => region.clone(),
+            None => imds.region()?,
+        };
+        let credentials = match credentials_from_env() {
+            Some(credentials) => credentials,
+            None => imds.credentials()?,
+        };
+        Ok(Session {
+            imds,
+            instance_id,
+            region,
+            credentials,
+        })
+    }
+
+    /// Signed POST of a Query API action, returns the response body
+    fn call(
+        &self,
+        endpoint: &str,
+        service: &str,
+        region: &str,
+        credentials: &Credentials,
+        params: &[(&str, &str)],
+    ) -> Result<String, String> {
+        let url = Url::parse(endpoint).map_err(|e| format!("Invalid endpoint {}: {}", endpoint, e))?;
+        let host = match (url.host_str(), url.port()) {
+            (Some(host), Some(port)) => format!("{}:{}", host, port),
+            (Some(host), None) => host.to_string(),
+            (None, _) => return Err(format!("Invalid endpoint {}: no host", endpoint)),
+        };
+        let body = form_encode(params);
+        let amz_date = Utc::now().format(sigv4::AMZ_DATE_FORMAT).to_string();
+
+        let mut headers = vec![
+            ("Content-Type", FORM_CONTENT_TYPE),
+            ("Host", host.as_str()),
+            ("X-Amz-Date", amz_date.as_str()),
+        ];
+        if let Some(ref token) = credentials.session_token {
+            headers.push(("X-Amz-Security-Token", token.as_str()));
+        }
+        let request = sigv4::Request {
+            method: "POST",
+            path: url.path(),
+            query: "",
+            headers: &headers,
+            body: body.as_bytes(),
+        };
+        let authorization = sigv4::authorization(&request, credentials, region, service, &amz_date);
+
+        let mut builder = self.client.post(url.clone());
+        for (name, value) in headers.iter().filter(|(name, _)| *name != "Host") {
+            builder = builder.header(*name, *value);
+        }
+        let mut response = builder
+            .header("Authorization", authorization)
+            .body(body)
+            .send()
+            .map_err(|e| format!("{} request to {} failed: {}", service, endpoint, e))?;
+        let text = response.text().unwrap_or_default();
+        if !response.status().is_success() {
+            return Err(format!(
+                "{} request to {} failed with {}: {}",
+                service,
+                endpoint,
+                response.status(),
+                text
+            ));
+        }
+        Ok(text)
+    }
+
+    /// Marks this instance Unhealthy in its Auto Scaling group, returns the instance id
+    pub fn set_instance_unhealthy(&self) -> Result<String, String> {
+        let session = self.session()?;
+        let endpoint = match self.config.asg.endpoint {
+            Some(ref endpoint) => endpoint.clone(),
+            None => format!("https://autoscaling.{}.amazonaws.com/", session.region),
+        };
+        let respect_grace_period = self.config.asg.respect_grace_period.to_string();
+        self.call(
+            &endpoint,
+            "autoscaling",
+            &session.region,
+            &session.credentials,
+            &[
+                ("Action", "SetInstanceHealth"),
+                ("HealthStatus", "Unhealthy"),
+                ("InstanceId", &session.instance_id),
+                ("ShouldRespectGracePeriod", &respect_grace_period),
+                ("Version", ASG_API_VERSION),
+            ],
+        )?;
+        Ok(session.instance_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use config::AwsAsgConfig;
+    use std::io::Read;
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::Duration;
+    use tiny_http::{Response, Server};
+
+    pub(crate) struct Recorded {
+        pub url: String,
+        pub authorization: String,
+        pub body: String,
+    }
+
+    // IMDS and the AWS APIs in one server, records the API calls
+    pub(crate) fn mock_server() -> (String, mpsc::Receiver<Recorded>) {
+        let server = Server::http("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", server.server_addr());
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            for mut request in server.incoming_requests() {
+                let header = |name: &str| {
+                    request
+                        .headers()
+                        .iter()
+                        .find(|header| header.field.as_str().as_str().eq_ignore_ascii_case(name))
+                        .map(|header| header.value.as_str().to_string())
+                };
+                let has_token = header(imds::TOKEN_HEADER) == Some("test-token".to_string());
+                let authorization = header("Authorization").unwrap_or_default();
+                let path = request.url().to_string();
+                let body = match path.as_str() {
+                    "/latest/api/token" => "test-token".to_string(),
+                    _ if path.starts_with("/latest/") && !has_token => {
+                        let _ = request.respond(Response::from_string("").with_status_code(401));
+                        continue;
+                    }
+                    "/latest/meta-data/instance-id" => "i-0123456789abcdef0".to_string(),
+                    "/latest/meta-data/placement/region" => "eu-west-1".to_string(),
+                    "/latest/meta-data/tags/instance/aws:autoscaling:groupName" => "web-asg".to_string(),
+                    "/latest/meta-data/iam/security-credentials/" => "checker-role\n".to_string(),
+                    "/latest/meta-data/iam/security-credentials/checker-role" => {
+                        r#"{"AccessKeyId":"AKIDTEST","SecretAccessKey":"secret","Token":"session"}"#.to_string()
+                    }
+                    path => {
+                        let mut body = String::new();
+                        request.as_reader().read_to_string(&mut body).unwrap();
+                        sender
+                            .send(Recorded {
+                                url: path.to_string(),
+                                authorization,
+                                body,
+                            })
+                            .unwrap();
+                        "<Response/>".to_string()
+                    }
+                };
+                let _ = request.respond(Response::from_string(body));
+            }
+        });
+        (url, receiver)
+    }
+
+    #[test]
+    fn set_instance_unhealthy_test() {
+        let (url, receiver) = mock_server();
+        let config = AwsConfig {
+            enabled: true,
+            region: None,
+            imds_url: url.clone(),
+            timeout: Duration::from_secs(5),
+            asg: AwsAsgConfig {
+                healthcheck: true,
+                endpoint: Some(format!("{}/asg", url)),
+                respect_grace_period: false,
+            },
+            cloudwatch: Default::default(),
+        };
+        let aws = Aws::new(&config).unwrap();
+        assert_eq!(aws.set_instance_unhealthy().unwrap(), "i-0123456789abcdef0");
+
+        let call = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(call.url, "/asg");
+        assert_eq!(
+            call.body,
+            "Action=SetInstanceHealth&HealthStatus=Unhealthy&InstanceId=i-0123456789abcdef0&\
+             ShouldRespectGracePeriod=false&Version=2011-01-01"
+        );
+        assert!(call.authorization.starts_with("AWS4-HMAC-SHA256 Credential="));
+        assert!(call.authorization.contains("/eu-west-1/autoscaling/aws4_request"));
+    }
+
+    #[test]
+    fn form_encode_test() {
+        assert_eq!(form_encode(&[("A", "b c"), ("D", "e/f")]), "A=b%20c&D=e%2Ff");
+    }
+}
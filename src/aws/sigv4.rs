@@ -0,0 +1,164 @@
+/* AWS Signature Version 4, just enough for the Query API calls of the checker.
+    https://docs.aws.amazon.com/general/latest/gr/sigv4_signing.html
+*/
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+
+pub const ALGORITHM: &str = "AWS4-HMAC-SHA256";
+// format of the x-amz-date header
+pub const AMZ_DATE_FORMAT: &str = "%Y%m%dT%H%M%SZ";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Credentials {
+    pub access_key_id: String,
+    pub secret_access_key: String,
+    pub session_token: Option<String>,
+}
+
+/// Request to sign. `headers` are the headers that are going to be sent, `host` and `x-amz-date` included
+pub struct Request<'a> {
+    pub method: &'a str,
+    pub path: &'a str,
+    pub query: &'a str,
+    pub headers: &'a [(&'a str, &'a str)],
+    pub body: &'a [u8],
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn hmac(key: &[u8], data: &str) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_varkey(key).expect("HMAC accepts keys of any size");
+    mac.input(data.as_bytes());
+    mac.result().code().to_vec()
+}
+
+/// Percent-encodes everything except the unreserved characters, as required by SigV4
+pub fn uri_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => (b as char).to_string(),
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+fn canonical_request(request: &Request) -> (String, String) {
+    let mut headers = request
+        .headers
+        .iter()
+        .map(|(name, value)| (name.to_lowercase(), value.trim()))
+        .collect::<Vec<_>>();
+    headers.sort();
+    let canonical_headers = headers
+        .iter()
+        .map(|(name, value)| format!("{}:{}\n", name, value))
+        .collect::<String>();
+    let signed_headers = headers
+        .iter()
+        .map(|(name, _)| name.as_str())
+        .collect::<Vec<_>>()
+        .join(";");
+    let canonical = format!(
+        "{}\n{}\n{}\n{}\n{}\n{}",
+        request.method,
+        request.path,
+        request.query,
+        canonical_headers,
+        signed_headers,
+        hex(&Sha256::digest(request.body))
+    );
+    (canonical, signed_headers)
+}
+
+/// Returns the value of the Authorization header for the request.
+/// `amz_date` must be the same value that is sent in the x-amz-date header.
+pub fn authorization(
+    request: &Request,
+    credentials: &Credentials,
+    region: &str,
+    service: &str,
+    amz_date: &str,
+) -> String {
+    let date = &amz_date[..8];
+    let scope = format!("{}/{}/{}/aws4_request", date, region, service);
+    let (canonical, signed_headers) = canonical_request(request);
+    let string_to_sign = format!(
+        "{}\n{}\n{}\n{}",
+        ALGORITHM,
+        amz_date,
+        scope,
+        hex(&Sha256::digest(canonical.as_bytes()))
+    );
+
+    let key = hmac(format!("AWS4{}", credentials.secret_access_key).as_bytes(), date);
+    let key = hmac(&key, region);
+    let key = hmac(&key, service);
+    let key = hmac(&key, "aws4_request");
+    let signature = hex(&hmac(&key, &string_to_sign));
+
+    format!(
+        "{} Credential={}/{}, SignedHeaders={}, Signature={}",
+        ALGORITHM, credentials.access_key_id, scope, signed_headers, signature
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // from the AWS SigV4 test suite
+    fn credentials() -> Credentials {
+        Credentials {
+            access_key_id: "AKIDEXAMPLE".to_string(),
+            secret_access_key: "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY".to_string(),
+            session_token: None,
+        }
+    }
+
+    #[test]
+    fn get_vanilla() {
+        let request = Request {
+            method: "GET",
+            path: "/",
+            query: "",
+            headers: &[("Host", "example.amazonaws.com"), ("X-Amz-Date", "20150830T123600Z")],
+            body: b"",
+        };
+        assert_eq!(
+            authorization(&request, &credentials(), "us-east-1", "service", "20150830T123600Z"),
+            "AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/20150830/us-east-1/service/aws4_request, \
+             SignedHeaders=host;x-amz-date, \
+             Signature=5fa00fa31553b73ebf1942676e86291e8372ff2a2260956d9b8aae1d763fbf31"
+        );
+    }
+
+    #[test]
+    fn post_x_www_form_urlencoded() {
+        let request = Request {
+            method: "POST",
+            path: "/",
+            query: "",
+            headers: &[
+                ("Content-Type", "application/x-www-form-urlencoded"),
+                ("Host", "example.amazonaws.com"),
+                ("X-Amz-Date", "20150830T123600Z"),
+            ],
+            body: b"Param1=value1",
+        };
+        assert_eq!(
+            authorization(&request, &credentials(), "us-east-1", "service", "20150830T123600Z"),
+            "AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/20150830/us-east-1/service/aws4_request, \
+             SignedHeaders=content-type;host;x-amz-date, \
+             Signature=ff11897932ad3f4e8b18135d722051e5ac45fc38421b1da7b9d196a0fe09473a"
+        );
+    }
+
+    #[test]
+    fn uri_encode_test() {
+        assert_eq!(uri_encode("i-0123abc_~."), "i-0123abc_~.");
+        assert_eq!(uri_encode("a b/c=d"), "a%20b%2Fc%3Dd");
+    }
+}
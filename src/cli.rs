@@ -0,0 +1,174 @@
+use clap::{App, Arg, ArgMatches, Error, SubCommand};
+use std::env;
+use std::ffi::OsString;
+
+pub const DEFAULT_CONFIG: &str = "settings";
+
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    // watch the containers, the default
+    Run,
+    // check the config and exit
+    Validate,
+    // send the dead letters again and exit
+    ReplayDeadLetters,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Args {
+    pub command: Command,
+    // path to the config file, with or without extension
+    pub config: String,
+    // overrides `logging.checker` from the config
+    pub log_level: Option<String>,
+    // run a single check cycle and exit
+    pub once: bool,
+    // log the actions instead of performing them
+    pub dry_run: bool,
+}
+
+fn config_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("config")
+        .short("c")
+        .long("config")
+        .value_name("PATH")
+        .help("Path to the config file")
+        .takes_value(true)
+        .default_value(DEFAULT_CONFIG)
+}
+
+fn app<'a, 'b>() -> App<'a, 'b> {
+    App::new("docker-check")
+        .version(env!("CARGO_PKG_VERSION"))
+        .about("Health-checker for docker containers")
+        .arg(config_arg())
+        .arg(
+            Arg::with_name("log-level")
+                .long("log-level")
+                .value_name("LEVEL")
+                .help("Log level of the checker, overrides the one from config")
+                .takes_value(true)
+                .possible_values(&["off", "error", "warn", "info", "debug", "trace"]),
+        )
+        .arg(
+            Arg::with_name("once")
+                .long("once")
+                .help("Run a single check cycle and exit with non-zero status if any container is unhealthy"),
+        )
+        .arg(
+            Arg::with_name("dry-run")
+                .long("dry-run")
+                .help("Only log the restarts and hooks instead of performing them"),
+        )
+        .subcommand(
+            SubCommand::with_name("validate")
+                .about("Check the config file and report every problem found")
+                .arg(config_arg()),
+        )
+        .subcommand(
+            SubCommand::with_name("replay-dead-letters")
+                .about("Send the events of the dead-letter log again, the ones that fail stay in the log")
+                .arg(config_arg()),
+        )
+}
+
+/// `--config` can be passed both before and after the subcommand, the latter wins
+fn config_from(matches: &ArgMatches, subcommand: Option<&ArgMatches>) -> String {
+    subcommand
+        .filter(|sub| sub.occurrences_of("config") > 0)
+        .and_then(|sub| sub.value_of("config"))
+        .or_else(|| matches.value_of("config"))
+        .unwrap_or(DEFAULT_CONFIG)
+        .to_string()
+}
+
+fn from_matches(matches: &ArgMatches) -> Args {
+    let (command, subcommand) = match matches.subcommand() {
+        ("validate", sub) => (Command::Validate, sub),
+        ("replay-dead-letters", sub) => (Command::ReplayDeadLetters, sub),
+        _ => (Command::Run, None),
+    };
+    Args {
+        command,
+        config: config_from(matches, subcommand),
+        log_level: matches.value_of("log-level").map(String::from),
+        once: matches.is_present("once"),
+        dry_run: matches.is_present("dry-run"),
+    }
+}
+
+/// Parses process arguments, exits with usage on error or `--help`
+pub fn parse() -> Args {
+    parse_from(env::args_os()).unwrap_or_else(|e| e.exit())
+}
+
+/// Parses the given arguments, the first one is the binary name. `--help` and `--version` are errors too
+pub fn parse_from<I, T>(args: I) -> Result<Args, Error>
+where
+    I: IntoIterator<Item = T>,
+    T: Into<OsString> + Clone,
+{
+    app().get_matches_from_safe(args).map(|matches| from_matches(&matches))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn defaults() {
+        let args = parse_from(vec!["docker-check"]).unwrap();
+        assert_eq!(
+            args,
+            Args {
+                command: Command::Run,
+                config: DEFAULT_CONFIG.to_string(),
+                log_level: None,
+                once: false,
+                dry_run: false,
+            }
+        );
+    }
+
+    #[test]
+    fn all_flags() {
+        let args = parse_from(vec![
+            "docker-check",
+            "--config",
+            "/etc/docker-check/settings.toml",
+            "--log-level",
+            "trace",
+            "--once",
+            "--dry-run",
+        ])
+        .unwrap();
+        assert_eq!(args.command, Command::Run);
+        assert_eq!(args.config, "/etc/docker-check/settings.toml");
+        assert_eq!(args.log_level, Some("trace".to_string()));
+        assert!(args.once);
+        assert!(args.dry_run);
+    }
+
+    #[test]
+    fn validate_subcommand() {
+        let args = parse_from(vec!["docker-check", "validate", "--config", "tests/settings"]).unwrap();
+        assert_eq!(args.command, Command::Validate);
+        assert_eq!(args.config, "tests/settings");
+
+        let args = parse_from(vec!["docker-check", "-c", "tests/settings", "validate"]).unwrap();
+        assert_eq!(args.command, Command::Validate);
+        assert_eq!(args.config, "tests/settings");
+    }
+
+    #[test]
+    fn replay_dead_letters_subcommand() {
+        let args = parse_from(vec!["docker-check", "replay-dead-letters", "-c", "tests/settings"]).unwrap();
+        assert_eq!(args.command, Command::ReplayDeadLetters);
+        assert_eq!(args.config, "tests/settings");
+    }
+
+    #[test]
+    fn invalid_log_level() {
+        assert!(parse_from(vec!["docker-check", "--log-level", "loud"]).is_err());
+    }
+}
@@ -1,5 +1,38 @@
+use endpoint;
+use humantime;
 use label_filters::LabelFilters;
+use notifiers::EventKind;
 use serde::{Deserialize, Deserializer};
+use std::collections::HashMap;
+use std::env;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Human readable duration, like "30s", "2m" or "1h 30m"
+pub fn parse_duration(value: &str) -> Result<Duration, String> {
+    humantime::parse_duration(value).map_err(|e| format!("Invalid duration \"{}\": {}", value, e))
+}
+
+fn deserialize_duration<'de, D>(deserializer: D) -> std::result::Result<Duration, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    use serde::de::Error;
+    let v = String::deserialize(deserializer)?;
+    parse_duration(&v).map_err(D::Error::custom)
+}
+
+fn deserialize_opt_duration<'de, D>(deserializer: D) -> std::result::Result<Option<Duration>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    use serde::de::Error;
+    let v = Option::<String>::deserialize(deserializer)?;
+    match v {
+        Some(v) => parse_duration(&v).map(Some).map_err(D::Error::custom),
+        None => Ok(None),
+    }
+}
 
 #[derive(Debug, Deserialize)]
 pub struct LoggingConfig {
@@ -7,14 +40,95 @@ pub struct LoggingConfig {
     pub default: String,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Clone)]
 pub struct DockerConfig {
-    pub connect_uri: String,
+    // resolved like the docker cli does when not set, see `endpoint::resolve`
+    pub connect_uri: Option<String>,
+    // effective endpoint, filled in by `get_settings`
+    #[serde(skip)]
+    pub endpoint: String,
+    #[serde(default)]
     pub tls: bool,
-    pub purge_unseen: u64,
+    // directory with ca.pem, cert.pem and key.pem, same as DOCKER_CERT_PATH
+    pub cert_path: Option<String>,
+    // paths to the separate files, take a priority over cert_path
+    pub tls_ca: Option<String>,
+    pub tls_cert: Option<String>,
+    pub tls_key: Option<String>,
+    #[serde(deserialize_with = "deserialize_duration")]
+    pub purge_unseen: Duration,
+    #[serde(default)]
+    pub use_events: bool,
+    #[serde(default = "default_reconcile_interval", deserialize_with = "deserialize_duration")]
+    pub reconcile_interval: Duration,
+    #[serde(default = "default_stop_timeout", deserialize_with = "deserialize_duration")]
+    pub stop_timeout: Duration,
 }
 
 #[derive(Debug, PartialEq)]
+pub struct TlsFiles {
+    pub ca: PathBuf,
+    // client certificate and key, None when they aren't configured and not in the certificates directory.
+    // The docker client requires both, `validate` and `get_new_client` reject TLS without them
+    pub cert: Option<PathBuf>,
+    pub key: Option<PathBuf>,
+}
+
+impl DockerConfig {
+    fn resolve_endpoint(&mut self) -> Result<(), String> {
+        if let Some(ref uri) = self.connect_uri {
+            self.endpoint = uri.clone();
+            return Ok(());
+        }
+        let endpoint = endpoint::resolve()?;
+        self.endpoint = endpoint.uri;
+        // TLS material of the docker context is used unless certificates are configured explicitly
+        if let Some(dir) = endpoint.tls_dir {
+            if self.cert_path.is_none() {
+                self.tls = true;
+                self.cert_path = Some(dir.to_string_lossy().into_owned());
+            }
+        }
+        Ok(())
+    }
+
+    /// TLS is enabled either by config or by non-empty DOCKER_TLS_VERIFY, like in the docker cli
+    pub fn tls_enabled(&self) -> bool {
+        self.tls || env::var("DOCKER_TLS_VERIFY").map(|v| !v.is_empty()).unwrap_or(false)
+    }
+
+    pub fn tls_files(&self) -> TlsFiles {
+        self.tls_files_from(env::var("DOCKER_CERT_PATH").ok(), env::var("HOME").ok())
+    }
+
+    /// Certificates directory is looked up in `cert_path`, then in DOCKER_CERT_PATH and then in ~/.docker.
+    /// The client certificate and key are None when the directory has only ca.pem, TLS can't be used then.
+    fn tls_files_from(&self, env_cert_path: Option<String>, home: Option<String>) -> TlsFiles {
+        let dir = match self.cert_path.clone().or(env_cert_path) {
+            Some(path) => PathBuf::from(path),
+            None => Path::new(&home.unwrap_or_default()).join(".docker"),
+        };
+        let client_file = |explicit: &Option<String>, name: &str| match explicit {
+            Some(path) => Some(PathBuf::from(path)),
+            None => Some(dir.join(name)).filter(|path| path.is_file()),
+        };
+        TlsFiles {
+            ca: self.tls_ca.as_ref().map_or_else(|| dir.join("ca.pem"), PathBuf::from),
+            cert: client_file(&self.tls_cert, "cert.pem"),
+            key: client_file(&self.tls_key, "key.pem"),
+        }
+    }
+}
+
+fn default_reconcile_interval() -> Duration {
+    Duration::from_secs(60)
+}
+
+fn default_stop_timeout() -> Duration {
+    Duration::from_secs(5)
+}
+
+#[derive(Debug, PartialEq, Clone)]
 pub struct ApplyTo {
     // Name,
     // Image,
@@ -30,8 +144,9 @@ impl<'de> Deserialize<'de> for ApplyTo {
     where
         D: Deserializer<'de>,
     {
+        use serde::de::Error;
         let v = Vec::<String>::deserialize(deserializer)?;
-        Ok(ApplyTo::from_vec(&v).unwrap())
+        ApplyTo::from_vec(&v).map_err(D::Error::custom)
     }
 }
 
@@ -41,21 +156,25 @@ impl ApplyTo {
         self.mask >> idx & 1 == 1
     }
 
-    fn from_vec(v: &Vec<String>) -> std::result::Result<Self, ()> {
+    pub(crate) fn from_vec(v: &Vec<String>) -> std::result::Result<Self, String> {
         let mut apply_bytes: u8 = 0b0000_0000;
         // Name, image, label
-        v.iter().for_each(|x| match x.as_str() {
-            "name" => {
-                apply_bytes |= 0b0000_0001;
-            }
-            "image" => {
-                apply_bytes |= 0b0000_0010;
+        for x in v.iter() {
+            match x.as_str() {
+                "name" => {
+                    apply_bytes |= 0b0000_0001;
+                }
+                "image" => {
+                    apply_bytes |= 0b0000_0010;
+                }
+                "label" => {
+                    apply_bytes |= 0b0000_0100;
+                }
+                _ => {
+                    return Err(format!("unknown value \"{}\", expected one of: name, image, label", x));
+                }
             }
-            "label" => {
-                apply_bytes |= 0b0000_0100;
-            }
-            _ => {}
-        });
+        }
 
         Ok(Self { mask: apply_bytes })
     }
@@ -82,17 +201,415 @@ pub struct ContainersConfig {
     pub run_on_failure: String,
     pub filter_self: Option<String>,
     pub(crate) label_filters: LabelFilters,
+    #[serde(default = "default_poll_interval", deserialize_with = "deserialize_duration")]
+    pub poll_interval: Duration,
+    #[serde(default)]
+    pub policy: Vec<PolicyConfig>,
+    // log restarts and hooks instead of performing them
+    #[serde(default)]
+    pub dry_run: bool,
+    // failing critical containers make /node-health of the status API fail
+    #[serde(default)]
+    pub critical: bool,
+    #[serde(default)]
+    pub hooks: HooksConfig,
+}
+
+/// Hooks of the container lifecycle events (`[containers.hooks]`), every one is optional.
+/// `run_on_failure` is used for hard failures when `on_hard_failure` isn't set.
+#[derive(Debug, Deserialize, Default, Clone, PartialEq)]
+pub struct HooksConfig {
+    // the first failed healthcheck
+    pub on_unhealthy: Option<String>,
+    pub on_restart: Option<String>,
+    // healthy again after a restart
+    pub on_recovered: Option<String>,
+    pub on_hard_failure: Option<String>,
+    // stats were purged after `docker.purge_unseen` or the container was destroyed
+    pub on_disappeared: Option<String>,
+    // overrides `[hooks] timeout` for these hooks
+    #[serde(default, deserialize_with = "deserialize_opt_duration")]
+    pub timeout: Option<Duration>,
 }
 
+impl HooksConfig {
+    pub fn get(&self, event: EventKind) -> Option<&str> {
+        let hook = match event {
+            EventKind::Unhealthy => &self.on_unhealthy,
+            EventKind::Restart => &self.on_restart,
+            EventKind::Recovered => &self.on_recovered,
+            EventKind::HardFailure => &self.on_hard_failure,
+            EventKind::Disappeared => &self.on_disappeared,
+        };
+        hook.as_ref().map(String::as_str)
+    }
+
+    pub fn get_mut(&mut self, event: EventKind) -> &mut Option<String> {
+        match event {
+            EventKind::Unhealthy => &mut self.on_unhealthy,
+            EventKind::Restart => &mut self.on_restart,
+            EventKind::Recovered => &mut self.on_recovered,
+            EventKind::HardFailure => &mut self.on_hard_failure,
+            EventKind::Disappeared => &mut self.on_disappeared,
+        }
+    }
+}
+
+/// Named policy group (`[[containers.policy]]`). Filters work the same way as the top-level ones,
+/// thresholds and hook that aren't set are taken from the `[containers]` section.
 #[derive(Debug, Deserialize)]
+pub struct PolicyConfig {
+    pub name: String,
+    pub filter_by: String,
+    pub apply_filter_to: ApplyTo,
+    pub filter_self: Option<String>,
+    #[serde(default)]
+    pub(crate) label_filters: LabelFilters,
+    pub consecutive_failures: Option<u16>,
+    pub hard_failures: Option<u16>,
+    pub run_on_failure: Option<String>,
+    #[serde(default, deserialize_with = "deserialize_opt_duration")]
+    pub stop_timeout: Option<Duration>,
+    pub critical: Option<bool>,
+    #[serde(default)]
+    pub hooks: HooksConfig,
+}
+
+fn default_poll_interval() -> Duration {
+    Duration::from_secs(2)
+}
+
+#[derive(Debug, Deserialize, Clone)]
 pub struct AwsAsgConfig {
+    // mark the instance Unhealthy in its Auto Scaling group when a container reaches hard_failures
     pub healthcheck: bool,
+    // Auto Scaling API endpoint, https://autoscaling.$region.amazonaws.com/ when unset
+    pub endpoint: Option<String>,
+    #[serde(default = "default_respect_grace_period")]
+    pub respect_grace_period: bool,
 }
 
-#[derive(Debug, Deserialize)]
+fn default_respect_grace_period() -> bool {
+    true
+}
+
+#[derive(Debug, Deserialize, Clone)]
 pub struct AwsConfig {
     pub enabled: bool,
+    // taken from IMDS when unset
+    pub region: Option<String>,
+    #[serde(default = "default_imds_url")]
+    pub imds_url: String,
+    // timeout of every request to IMDS and AWS APIs
+    #[serde(default = "default_aws_timeout", deserialize_with = "deserialize_duration")]
+    pub timeout: Duration,
     pub asg: AwsAsgConfig,
+    #[serde(default)]
+    pub cloudwatch: CloudWatchConfig,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Dimension {
+    InstanceId,
+    AsgName,
+    ContainerName,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct CloudWatchConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default = "default_cloudwatch_namespace")]
+    pub namespace: String,
+    #[serde(default = "default_cloudwatch_interval", deserialize_with = "deserialize_duration")]
+    pub interval: Duration,
+    #[serde(default = "default_cloudwatch_dimensions")]
+    pub dimensions: Vec<Dimension>,
+    // taken from the aws:autoscaling:groupName instance tag when unset
+    pub asg_name: Option<String>,
+    // CloudWatch API endpoint, https://monitoring.$region.amazonaws.com/ when unset
+    pub endpoint: Option<String>,
+}
+
+impl Default for CloudWatchConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            namespace: default_cloudwatch_namespace(),
+            interval: default_cloudwatch_interval(),
+            dimensions: default_cloudwatch_dimensions(),
+            asg_name: None,
+            endpoint: None,
+        }
+    }
+}
+
+fn default_cloudwatch_namespace() -> String {
+    "DockerCheck".to_string()
+}
+
+fn default_cloudwatch_interval() -> Duration {
+    Duration::from_secs(60)
+}
+
+fn default_cloudwatch_dimensions() -> Vec<Dimension> {
+    vec![Dimension::InstanceId, Dimension::AsgName, Dimension::ContainerName]
+}
+
+fn default_imds_url() -> String {
+    "http://169.254.169.254".to_string()
+}
+
+fn default_aws_timeout() -> Duration {
+    Duration::from_secs(5)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MetricsConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default = "default_metrics_listen")]
+    pub listen: String,
+    // write metrics for the node_exporter textfile collector into this directory every tick
+    pub textfile_dir: Option<String>,
+}
+
+impl Default for MetricsConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            listen: default_metrics_listen(),
+            textfile_dir: None,
+        }
+    }
+}
+
+fn default_metrics_listen() -> String {
+    "127.0.0.1:9102".to_string()
+}
+
+#[derive(Debug, Deserialize)]
+pub struct StatusConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default = "default_status_listen")]
+    pub listen: String,
+}
+
+impl Default for StatusConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            listen: default_status_listen(),
+        }
+    }
+}
+
+fn default_status_listen() -> String {
+    "127.0.0.1:9103".to_string()
+}
+
+/// Execution limits of the hooks (`[hooks]`)
+#[derive(Debug, Deserialize, Clone)]
+pub struct HookRunnerConfig {
+    // the whole process group of the hook is killed after it
+    #[serde(default = "default_hook_timeout", deserialize_with = "deserialize_duration")]
+    pub timeout: Duration,
+    // hooks running at the same time, the rest wait in the queue
+    #[serde(default = "default_hook_max_concurrent")]
+    pub max_concurrent: usize,
+    // hooks that don't fit into the queue are dropped, at least 1
+    #[serde(default = "default_hook_queue_size")]
+    pub queue_size: usize,
+    // a hook that exited with non-zero status, was killed or timed out is run again `retries` times
+    #[serde(default)]
+    pub retries: u32,
+    #[serde(default = "default_backoff", deserialize_with = "deserialize_duration")]
+    pub backoff: Duration,
+    #[serde(default = "default_jitter")]
+    pub jitter: f64,
+    // output of every hook execution goes to a file in it, the main log only references the file
+    #[serde(default = "default_hook_log_dir")]
+    pub log_dir: String,
+    // bytes of the output kept per attempt, the beginning is cut
+    #[serde(default = "default_hook_max_log_size")]
+    pub max_log_size: usize,
+    // the oldest files are removed
+    #[serde(default = "default_hook_keep_logs")]
+    pub keep_logs: usize,
+}
+
+impl Default for HookRunnerConfig {
+    fn default() -> Self {
+        Self {
+            timeout: default_hook_timeout(),
+            max_concurrent: default_hook_max_concurrent(),
+            queue_size: default_hook_queue_size(),
+            retries: 0,
+            backoff: default_backoff(),
+            jitter: default_jitter(),
+            log_dir: default_hook_log_dir(),
+            max_log_size: default_hook_max_log_size(),
+            keep_logs: default_hook_keep_logs(),
+        }
+    }
+}
+
+fn default_hook_timeout() -> Duration {
+    Duration::from_secs(60)
+}
+
+fn default_hook_max_concurrent() -> usize {
+    4
+}
+
+fn default_hook_queue_size() -> usize {
+    32
+}
+
+// absolute, the working directory of a daemon is usually `/`
+fn default_hook_log_dir() -> String {
+    "/var/log/docker-check/hooks".to_string()
+}
+
+fn default_hook_max_log_size() -> usize {
+    64 * 1024
+}
+
+fn default_hook_keep_logs() -> usize {
+    1000
+}
+
+fn default_backoff() -> Duration {
+    Duration::from_secs(1)
+}
+
+// ±20% of every retry delay
+fn default_jitter() -> f64 {
+    0.2
+}
+
+/// Events that couldn't be delivered after all the retries (`[dead_letters]`)
+#[derive(Debug, Deserialize, Default, Clone)]
+pub struct DeadLettersConfig {
+    // JSON lines file, dead letters are dropped when unset
+    pub path: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum NotifierKind {
+    Webhook,
+    Pagerduty,
+    Alertmanager,
+    Email,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct SmtpConfig {
+    pub host: String,
+    #[serde(default = "default_smtp_port")]
+    pub port: u16,
+    // upgrade the connection with STARTTLS, the message isn't sent if the relay doesn't support it
+    #[serde(default = "default_starttls")]
+    pub starttls: bool,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub from: String,
+    pub to: Vec<String>,
+}
+
+fn default_smtp_port() -> u16 {
+    587
+}
+
+fn default_starttls() -> bool {
+    true
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum MessageFormat {
+    Json,
+    Slack,
+    Teams,
+    Discord,
+}
+
+impl Default for MessageFormat {
+    fn default() -> Self {
+        MessageFormat::Json
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct NotifierConfig {
+    // used in logs, `notifiers[<index>]` when unset
+    pub name: Option<String>,
+    #[serde(rename = "type")]
+    pub kind: NotifierKind,
+    // webhook URL, Alertmanager base URL, or PagerDuty Events API URL to override the default one
+    pub url: Option<String>,
+    // integration key of the PagerDuty service
+    pub routing_key: Option<String>,
+    // relay of the email notifier
+    pub smtp: Option<SmtpConfig>,
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+    // payload of the webhook
+    #[serde(default)]
+    pub format: MessageFormat,
+    #[serde(default = "default_notifier_timeout", deserialize_with = "deserialize_duration")]
+    pub timeout: Duration,
+    #[serde(default = "default_notifier_retries")]
+    pub retries: u32,
+    // delay before the first retry, doubled for every next one
+    #[serde(default = "default_backoff", deserialize_with = "deserialize_duration")]
+    pub backoff: Duration,
+    #[serde(default = "default_jitter")]
+    pub jitter: f64,
+    // lifecycle events the notifier is interested in
+    #[serde(default = "default_notifier_events")]
+    pub events: Vec<EventKind>,
+}
+
+impl Default for NotifierConfig {
+    fn default() -> Self {
+        Self {
+            name: None,
+            kind: NotifierKind::Webhook,
+            url: None,
+            routing_key: None,
+            smtp: None,
+            headers: HashMap::new(),
+            format: MessageFormat::Json,
+            timeout: default_notifier_timeout(),
+            retries: default_notifier_retries(),
+            backoff: default_backoff(),
+            jitter: default_jitter(),
+            events: default_notifier_events(),
+        }
+    }
+}
+
+fn default_notifier_timeout() -> Duration {
+    Duration::from_secs(5)
+}
+
+fn default_notifier_retries() -> u32 {
+    3
+}
+
+fn default_notifier_events() -> Vec<EventKind> {
+    EventKind::ALL.to_vec()
+}
+
+#[derive(Debug, Deserialize, Default)]
+pub struct ReloadConfig {
+    // reload when the config file is modified, in addition to SIGHUP
+    #[serde(default)]
+    pub watch_file: bool,
 }
 
 #[derive(Debug, Deserialize)]
@@ -101,6 +618,23 @@ pub struct Config {
     pub docker: DockerConfig,
     pub containers: ContainersConfig,
     pub aws: AwsConfig,
+    #[serde(default)]
+    pub reload: ReloadConfig,
+    #[serde(default)]
+    pub metrics: MetricsConfig,
+    #[serde(default)]
+    pub status: StatusConfig,
+    #[serde(default)]
+    pub notifiers: Vec<NotifierConfig>,
+    #[serde(default)]
+    pub hooks: HookRunnerConfig,
+    #[serde(default)]
+    pub dead_letters: DeadLettersConfig,
+}
+
+/// Settings from the environment, they override the config file
+pub(crate) fn environment() -> configuration::Environment {
+    configuration::Environment::with_prefix("APP")
 }
 
 pub fn get_settings(filename: &str) -> Result<Config, String> {
@@ -111,12 +645,17 @@ pub fn get_settings(filename: &str) -> Result<Config, String> {
         .map_err(|e| e.to_string())?
         // Add in settings from the environment (with a prefix of APP)
         // Eg.. `APP_DEBUG=1 ./target/app` would set the `debug` key
-        .merge(configuration::Environment::with_prefix("APP"))
+        .merge(environment())
         .map_err(|e| e.to_string())?;
 
-    Ok(settings
+    let mut config = settings
         .try_into::<Config>()
-        .map_err(|e| format!("Cannot parse config correctly! Nested error: {}", e.to_string()))?)
+        .map_err(|e| format!("Cannot parse config correctly! Nested error: {}", e.to_string()))?;
+    config
+        .docker
+        .resolve_endpoint()
+        .map_err(|e| format!("Cannot resolve docker endpoint: {}", e))?;
+    Ok(config)
 }
 
 #[cfg(test)]
@@ -127,6 +666,131 @@ mod tests {
         get_settings("settings").unwrap();
     }
 
+    #[test]
+    fn durations_should_be_parsed() {
+        let settings = get_settings("tests/settings").unwrap();
+        assert_eq!(settings.containers.poll_interval, Duration::from_secs(2));
+        assert_eq!(settings.docker.stop_timeout, Duration::from_secs(30));
+        assert_eq!(settings.docker.purge_unseen, Duration::from_secs(100));
+        assert_eq!(settings.docker.reconcile_interval, Duration::from_secs(60));
+        assert_eq!(settings.hooks.timeout, Duration::from_secs(10));
+        assert_eq!(settings.hooks.max_concurrent, 2);
+        assert_eq!(settings.hooks.retries, 1);
+        assert_eq!(settings.hooks.backoff, Duration::from_secs(2));
+        assert_eq!(settings.hooks.jitter, 0.1);
+        assert_eq!(settings.hooks.log_dir, "/tmp/docker-check/hooks");
+        assert_eq!(settings.hooks.max_log_size, 4096);
+        assert_eq!(settings.hooks.keep_logs, 1000);
+        assert_eq!(settings.dead_letters.path, None);
+        assert_eq!(settings.containers.hooks.timeout, None);
+        assert_eq!(
+            settings.containers.policy[0].hooks.timeout,
+            Some(Duration::from_secs(120))
+        );
+        assert_eq!(parse_duration("2m").unwrap(), Duration::from_secs(120));
+        assert!(parse_duration("two minutes").is_err());
+    }
+
+    #[test]
+    fn endpoint_from_connect_uri() {
+        let settings = get_settings("tests/settings").unwrap();
+        assert_eq!(settings.docker.endpoint, "unix:///var/run/docker.sock");
+    }
+
+    #[test]
+    fn tls_files_test() {
+        let mut settings = get_settings("tests/settings").unwrap();
+        let files = settings
+            .docker
+            .tls_files_from(Some("/etc/docker/certs".to_string()), Some("/root".to_string()));
+        assert_eq!(files.ca, PathBuf::from("/etc/docker/certs/ca.pem"));
+        // missing client certificate and key are left out
+        assert_eq!(files.key, None);
+
+        let files = settings.docker.tls_files_from(None, Some("/root".to_string()));
+        assert_eq!(files.ca, PathBuf::from("/root/.docker/ca.pem"));
+
+        // server-only TLS, the context has only the CA
+        let dir =
+            "tests/docker-config/contexts/tls/b71199ebd070b36beab7317920c2c2f1d777df8d05e5527d8458fda57cb17a7a/docker";
+        let files = settings.docker.tls_files_from(Some(dir.to_string()), None);
+        assert!(files.ca.is_file());
+        assert_eq!((files.cert, files.key), (None, None));
+
+        settings.docker.cert_path = Some("tests/certs".to_string());
+        settings.docker.tls_key = Some("/secret/key.pem".to_string());
+        let files = settings
+            .docker
+            .tls_files_from(Some("/etc/docker/certs".to_string()), None);
+        assert_eq!(files.ca, PathBuf::from("tests/certs/ca.pem"));
+        assert_eq!(files.key, Some(PathBuf::from("/secret/key.pem")));
+    }
+
+    #[test]
+    fn policy_groups_should_be_parsed() {
+        let settings = get_settings("tests/settings").unwrap();
+        assert_eq!(settings.containers.policy.len(), 1);
+        let group = &settings.containers.policy[0];
+        assert_eq!(group.name, "databases");
+        assert!(group.apply_filter_to.should_filter_images());
+        assert_eq!(group.consecutive_failures, Some(10));
+        assert_eq!(group.run_on_failure, None);
+        assert_eq!(group.stop_timeout, Some(Duration::from_secs(60)));
+        assert_eq!(group.hooks.on_unhealthy, Some("example/notify-slack.sh".to_string()));
+        assert_eq!(group.hooks.on_restart, None);
+        assert_eq!(
+            settings.containers.hooks.on_disappeared,
+            Some("example/notify-slack.sh {id} {event}".to_string())
+        );
+        assert_eq!(settings.containers.hooks.on_hard_failure, None);
+    }
+
+    #[test]
+    fn aws_should_be_parsed() {
+        let settings = get_settings("tests/settings").unwrap();
+        assert_eq!(settings.aws.region, Some("eu-west-1".to_string()));
+        assert_eq!(settings.aws.imds_url, "http://169.254.169.254");
+        assert_eq!(settings.aws.timeout, Duration::from_secs(3));
+        assert_eq!(settings.aws.asg.endpoint, Some("http://127.0.0.1:4566/".to_string()));
+        assert!(settings.aws.asg.respect_grace_period);
+        assert_eq!(settings.aws.cloudwatch.namespace, "Docker/Check");
+        assert_eq!(
+            settings.aws.cloudwatch.dimensions,
+            vec![Dimension::InstanceId, Dimension::ContainerName]
+        );
+        assert_eq!(settings.aws.cloudwatch.interval, Duration::from_secs(60));
+    }
+
+    #[test]
+    fn notifiers_should_be_parsed() {
+        let settings = get_settings("tests/settings").unwrap();
+        let notifier = &settings.notifiers[0];
+        assert_eq!(notifier.kind, NotifierKind::Webhook);
+        assert_eq!(notifier.format, MessageFormat::Json);
+        assert_eq!(settings.notifiers[1].format, MessageFormat::Slack);
+        assert_eq!(
+            settings.notifiers[1].events,
+            vec![EventKind::Restart, EventKind::Recovered, EventKind::HardFailure]
+        );
+        assert_eq!(notifier.events, EventKind::ALL.to_vec());
+        assert_eq!(settings.notifiers[2].kind, NotifierKind::Pagerduty);
+        assert_eq!(settings.notifiers[2].routing_key, Some("R0UT1NG".to_string()));
+        assert_eq!(settings.notifiers[3].kind, NotifierKind::Alertmanager);
+        let smtp = settings.notifiers[4].smtp.as_ref().unwrap();
+        assert_eq!(smtp.port, 587);
+        assert!(smtp.starttls);
+        assert_eq!(smtp.to, vec!["ops@example.com", "oncall@example.com"]);
+        // header names are case-insensitive, the config may lowercase them
+        assert!(notifier
+            .headers
+            .iter()
+            .any(|(name, value)| name.eq_ignore_ascii_case("authorization") && value == "Bearer secret"));
+        assert_eq!(notifier.timeout, Duration::from_secs(5));
+        assert_eq!(notifier.retries, 2);
+        assert_eq!(notifier.backoff, Duration::from_millis(500));
+        assert_eq!(notifier.jitter, 0.5);
+    }
+
     #[test]
     fn apply_to_test() {
         let mut v = Vec::new();
@@ -148,4 +812,10 @@ mod tests {
         assert!(apply_to.should_filter_names() == true);
         assert!(apply_to.should_filter_labels() == true);
     }
+
+    #[test]
+    fn apply_to_unknown_value() {
+        let v = vec!["name".to_string(), "names".to_string()];
+        assert!(ApplyTo::from_vec(&v).unwrap_err().contains("\"names\""));
+    }
 }
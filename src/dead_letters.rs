@@ -0,0 +1,493 @@
+/* Dead-letter log: events that a notifier or a hook couldn't deliver after all the retries
+    are appended to `[dead_letters] path`, one JSON document per line.
+    `docker-check replay-dead-letters` sends them again: the log is moved aside to `<path>.replay`,
+    and whatever fails again is appended back to the log, so the running checker can keep writing to it.
+    PagerDuty and Alertmanager keep the state of an incident, so their hard failures and resolutions
+    followed by a newer one of the same container and notifier in the log are dropped,
+    replaying them would e.g. trigger an incident again after it was resolved.
+*/
+use chrono::{DateTime, FixedOffset, Utc};
+use config::{self, Config, DeadLettersConfig, NotifierKind};
+use hooks::{self, Hook};
+use humantime;
+use notifiers::{self, Event, EventKind};
+use retry::Retry;
+use run_command::{self, HookError};
+use serde_json;
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::sync::Mutex;
+
+lazy_static! {
+    // notifier and hook threads append to the same file
+    static ref WRITE_LOCK: Mutex<()> = Mutex::new(());
+}
+
+/// Who failed to deliver the events
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Target {
+    // by `name` of the notifier, or `notifiers[<index>]`
+    Notifier {
+        name: String,
+    },
+    // timeout of the hook as resolved by the policy, like "30s"; `[hooks] timeout` when it's missing
+    Hook {
+        template: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        timeout: Option<String>,
+    },
+}
+
+impl Target {
+    pub fn hook(hook: &Hook) -> Self {
+        Target::Hook {
+            template: hook.template.clone(),
+            timeout: Some(humantime::format_duration(hook.timeout).to_string()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeadLetter {
+    // RFC 3339, when the last attempt has failed
+    pub time: String,
+    pub target: Target,
+    pub error: String,
+    pub events: Vec<Event>,
+}
+
+impl DeadLetter {
+    pub fn new(target: Target, error: &str, events: Vec<Event>) -> Self {
+        Self {
+            time: Utc::now().to_rfc3339(),
+            target,
+            error: error.to_string(),
+            events,
+        }
+    }
+}
+
+fn append_line(path: &str, line: &str) -> Result<(), String> {
+    let _lock = WRITE_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(|e| format!("Cannot open {}: {}", path, e))?;
+    // a single write, so lines of several processes don't interleave
+    file.write_all(format!("{}\n", line).as_bytes())
+        .map_err(|e| format!("Cannot write to {}: {}", path, e))
+}
+
+pub fn append(path: &str, letter: &DeadLetter) -> Result<(), String> {
+    let line = serde_json::to_string(letter).map_err(|e| e.to_string())?;
+    append_line(path, &line)
+}
+
+/// Appends the dead letter to the log, or only reports it if the log is disabled
+pub fn store(config: &DeadLettersConfig, letter: DeadLetter) {
+    match config.path {
+        Some(ref path) => match append(path, &letter) {
+            Ok(()) => info!(
+                "{} event(s) were written to the dead-letter log {}",
+                letter.events.len(),
+                path
+            ),
+            Err(e) => error!("{} event(s) are lost: {}", letter.events.len(), e),
+        },
+        None => warn!(
+            "{} event(s) are lost, set `dead_letters.path` to keep them",
+            letter.events.len()
+        ),
+    }
+}
+
+fn replay_letter(config: &Config, letter: &DeadLetter) -> Result<(), String> {
+    match letter.target {
+        Target::Notifier { ref name } => {
+            let (idx, notifier_config) = config
+                .notifiers
+                .iter()
+                .enumerate()
+                .find(|(idx, notifier)| notifiers::notifier_name(*idx, notifier) == *name)
+                .ok_or_else(|| format!("notifier {} isn't configured anymore", name))?;
+            let notifier = notifiers::build(notifier_config).map_err(|e| {
+                format!(
+                    "Cannot create notifier {}: {}",
+                    notifiers::notifier_name(idx, notifier_config),
+                    e
+                )
+            })?;
+            let retry = Retry::new(notifier_config.retries, notifier_config.backoff, notifier_config.jitter);
+            notifiers::deliver(notifier.as_ref(), &letter.events, &retry)
+        }
+        Target::Hook {
+            ref template,
+            ref timeout,
+        } => {
+            let timeout = match timeout {
+                Some(timeout) => config::parse_duration(timeout)?,
+                None => config.hooks.timeout,
+            };
+            let retry = Retry::new(config.hooks.retries, config.hooks.backoff, config.hooks.jitter);
+            for event in letter.events.iter() {
+                let (cmd, args) = hooks::command(template, event)?;
+                let input = serde_json::to_vec(event).unwrap_or_default();
+                let env = hooks::env(event);
+                retry
+                    .run(
+                        || run_command::run_command(&cmd, &args, &env, &input, timeout),
+                        HookError::is_retryable,
+                    )
+                    .map_err(|e| format!("Script \"{}\" {}", cmd, e))?;
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Outcome of a replay
+#[derive(Debug, Default, PartialEq)]
+pub struct Replayed {
+    pub delivered: usize,
+    // kept in the log to be replayed again
+    pub failed: usize,
+    // events that weren't sent because a newer one of the same incident was in the log
+    pub superseded: usize,
+}
+
+fn event_time(event: &Event) -> Option<DateTime<FixedOffset>> {
+    DateTime::parse_from_rfc3339(&event.time).ok()
+}
+
+/// Names of the notifiers that trigger and resolve incidents, the others get every event
+fn incident_notifiers(config: &Config) -> HashSet<String> {
+    config
+        .notifiers
+        .iter()
+        .enumerate()
+        .filter(|(_, notifier)| match notifier.kind {
+            NotifierKind::Pagerduty | NotifierKind::Alertmanager => true,
+            NotifierKind::Webhook | NotifierKind::Email => false,
+        })
+        .map(|(idx, notifier)| notifiers::notifier_name(idx, notifier))
+        .collect()
+}
+
+/// Events that trigger or resolve an incident
+fn changes_incident(event: &Event) -> bool {
+    match event.event {
+        EventKind::HardFailure | EventKind::Recovered | EventKind::Disappeared => true,
+        EventKind::Unhealthy | EventKind::Restart => false,
+    }
+}
+
+/// Time of the newest incident event of every container by notifier
+fn newest_events(
+    letters: &[&DeadLetter],
+    incident_notifiers: &HashSet<String>,
+) -> HashMap<(String, String), DateTime<FixedOffset>> {
+    let mut newest = HashMap::new();
+    for letter in letters.iter() {
+        let name = match letter.target {
+            Target::Notifier { ref name } if incident_notifiers.contains(name) => name,
+            _ => continue,
+        };
+        for event in letter.events.iter().filter(|event| changes_incident(event)) {
+            if let Some(time) = event_time(event) {
+                let entry = newest.entry((name.clone(), event.id.clone())).or_insert(time);
+                if time > *entry {
+                    *entry = time;
+                }
+            }
+        }
+    }
+    newest
+}
+
+/// Drops the incident events that have a newer incident event of the same container in the log,
+/// returns the number of the dropped ones. `newest` only has the notifiers that keep incidents.
+fn drop_superseded(letter: &mut DeadLetter, newest: &HashMap<(String, String), DateTime<FixedOffset>>) -> usize {
+    let name = match letter.target {
+        Target::Notifier { ref name } => name.clone(),
+        Target::Hook { .. } => return 0,
+    };
+    let before = letter.events.len();
+    letter.events.retain(|event| {
+        if !changes_incident(event) {
+            return true;
+        }
+        match (event_time(event), newest.get(&(name.clone(), event.id.clone()))) {
+            (Some(time), Some(newest)) => time >= *newest,
+            _ => true,
+        }
+    });
+    let dropped = before - letter.events.len();
+    if dropped > 0 {
+        info!(
+            "Dropping {} event(s) of notifier {} superseded by newer ones",
+            dropped, name
+        );
+    }
+    dropped
+}
+
+/// Sends the dead letters again.
+/// When the log can't be written, the letters that weren't handled yet are left in `<path>.replay`.
+pub fn replay(config: &Config) -> Result<Replayed, String> {
+    let path = config
+        .dead_letters
+        .path
+        .as_ref()
+        .ok_or_else(|| "`dead_letters.path` is not set".to_string())?;
+    let replay_path = format!("{}.replay", path);
+    // leftovers of an interrupted replay are sent first, the log is taken on the next run then
+    if !Path::new(&replay_path).exists() {
+        if !Path::new(path).exists() {
+            return Ok(Replayed::default());
+        }
+        fs::rename(path, &replay_path).map_err(|e| format!("Cannot move {} to {}: {}", path, replay_path, e))?;
+    }
+    let content = fs::read_to_string(&replay_path).map_err(|e| format!("Cannot read {}: {}", replay_path, e))?;
+    let lines = content
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(idx, line)| (idx, line, serde_json::from_str::<DeadLetter>(line)))
+        .collect::<Vec<_>>();
+    let newest = newest_events(
+        &lines
+            .iter()
+            .filter_map(|(_, _, letter)| letter.as_ref().ok())
+            .collect::<Vec<_>>(),
+        &incident_notifiers(config),
+    );
+    let mut replayed = Replayed::default();
+    for (pos, (idx, line, letter)) in lines.iter().enumerate() {
+        let kept = match letter {
+            Err(e) => {
+                error!(
+                    "{}:{} is not a valid dead letter, keeping it: {}",
+                    replay_path,
+                    idx + 1,
+                    e
+                );
+                replayed.failed += 1;
+                append_line(path, line)
+            }
+            Ok(letter) => {
+                let mut letter = letter.clone();
+                replayed.superseded += drop_superseded(&mut letter, &newest);
+                if letter.events.is_empty() {
+                    continue;
+                }
+                match replay_letter(config, &letter) {
+                    Ok(()) => {
+                        replayed.delivered += 1;
+                        Ok(())
+                    }
+                    Err(e) => {
+                        error!(
+                            "Cannot replay {} event(s) of {:?}: {}",
+                            letter.events.len(),
+                            letter.target,
+                            e
+                        );
+                        replayed.failed += 1;
+                        letter.time = Utc::now().to_rfc3339();
+                        letter.error = e;
+                        append(path, &letter)
+                    }
+                }
+            }
+        };
+        if let Err(e) = kept {
+            // this and the following letters are replayed on the next run, the delivered ones are gone
+            let rest = lines[pos..]
+                .iter()
+                .map(|(_, line, _)| format!("{}\n", line))
+                .collect::<String>();
+            if let Err(write_error) = fs::write(&replay_path, rest) {
+                error!("Cannot rewrite {}: {}", replay_path, write_error);
+            }
+            return Err(e);
+        }
+    }
+    fs::remove_file(&replay_path).map_err(|e| format!("Cannot remove {}: {}", replay_path, e))?;
+    Ok(replayed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use config::{self, NotifierConfig};
+    use notifiers::tests::event;
+    use std::env;
+    use std::process;
+    use std::time::Duration;
+
+    fn temp_log(name: &str) -> String {
+        let path = env::temp_dir().join(format!("docker-check-{}-{}.jsonl", name, process::id()));
+        let _ = fs::remove_file(&path);
+        path.to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn append_test() {
+        let path = temp_log("append");
+        let target = Target::Notifier {
+            name: "ops".to_string(),
+        };
+        append(&path, &DeadLetter::new(target.clone(), "HTTP 502", vec![event()])).unwrap();
+        append(
+            &path,
+            &DeadLetter::new(target.clone(), "HTTP 503", vec![event(), event()]),
+        )
+        .unwrap();
+
+        let content = fs::read_to_string(&path).unwrap();
+        let letters = content
+            .lines()
+            .map(|line| serde_json::from_str::<DeadLetter>(line).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(letters.len(), 2);
+        assert_eq!(letters[0].target, target);
+        assert_eq!(letters[1].error, "HTTP 503");
+        assert_eq!(letters[1].events[1].name, "/db");
+        let raw: serde_json::Value = serde_json::from_str(content.lines().next().unwrap()).unwrap();
+        assert_eq!(raw["target"], json!({"type": "notifier", "name": "ops"}));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn replay_test() {
+        let path = temp_log("replay");
+        let mut settings = config::get_settings("tests/settings").unwrap();
+        settings.dead_letters.path = Some(path.clone());
+        settings.notifiers = vec![
+            NotifierConfig {
+                name: Some("unreachable".to_string()),
+                kind: NotifierKind::Pagerduty,
+                url: Some("http://127.0.0.1:9/enqueue".to_string()),
+                routing_key: Some("R0UT1NG".to_string()),
+                retries: 0,
+                ..Default::default()
+            },
+            NotifierConfig {
+                name: Some("webhook".to_string()),
+                url: Some("http://127.0.0.1:9/hook".to_string()),
+                retries: 0,
+                ..Default::default()
+            },
+        ];
+        let hook = |template: &str| {
+            Target::hook(&Hook {
+                template: template.to_string(),
+                timeout: Duration::from_secs(10),
+            })
+        };
+        append(
+            &path,
+            &DeadLetter::new(hook("tests/run_command.sh"), "timed out", vec![event()]),
+        )
+        .unwrap();
+        let mut failing = event();
+        failing.id = "some-id-error".to_string();
+        append(
+            &path,
+            &DeadLetter::new(hook("tests/run_command.sh"), "exited with code 1", vec![failing]),
+        )
+        .unwrap();
+        let mut restart = event();
+        restart.event = EventKind::Restart;
+        let mut recovered = event();
+        recovered.event = EventKind::Recovered;
+        recovered.time = "2019-01-01T00:05:00+00:00".to_string();
+        let pagerduty = Target::Notifier {
+            name: "unreachable".to_string(),
+        };
+        append(
+            &path,
+            &DeadLetter::new(pagerduty.clone(), "connection refused", vec![restart, event()]),
+        )
+        .unwrap();
+        // resolves the incident of the hard failure above, which isn't triggered again
+        append(
+            &path,
+            &DeadLetter::new(pagerduty, "connection refused", vec![recovered.clone()]),
+        )
+        .unwrap();
+        // a webhook gets every event
+        let webhook = Target::Notifier {
+            name: "webhook".to_string(),
+        };
+        append(
+            &path,
+            &DeadLetter::new(webhook.clone(), "connection refused", vec![event()]),
+        )
+        .unwrap();
+        append(&path, &DeadLetter::new(webhook, "connection refused", vec![recovered])).unwrap();
+        append_line(&path, "not json").unwrap();
+
+        assert_eq!(
+            replay(&settings).unwrap(),
+            // the restart is left alone, PagerDuty ignores it without a request
+            Replayed {
+                delivered: 2,
+                failed: 5,
+                superseded: 1,
+            }
+        );
+        assert!(!Path::new(&format!("{}.replay", path)).exists());
+        let content = fs::read_to_string(&path).unwrap();
+        let lines = content.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 5);
+        assert!(lines[0].contains("exited with code 1"));
+        assert!(lines[0].contains("\"timeout\":\"10s\""));
+        assert!(lines[1].contains("\"name\":\"unreachable\""));
+        assert!(lines[1].contains("\"event\":\"recovered\""));
+        assert!(lines[2].contains("\"event\":\"hard_failure\""));
+        assert!(lines[3].contains("\"event\":\"recovered\""));
+        assert_eq!(lines[4], "not json");
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn replay_keeps_letters_when_log_cannot_be_written() {
+        let path = temp_log("replay-unwritable");
+        let replay_path = format!("{}.replay", path);
+        let mut settings = config::get_settings("tests/settings").unwrap();
+        settings.dead_letters.path = Some(path.clone());
+        // leftovers of an interrupted replay, while the log itself can't be opened
+        fs::create_dir_all(&path).unwrap();
+        let target = Target::Hook {
+            template: "tests/run_command.sh".to_string(),
+            timeout: None,
+        };
+        let mut failing = event();
+        failing.id = "some-id-error".to_string();
+        append(
+            &replay_path,
+            &DeadLetter::new(target.clone(), "timed out", vec![event()]),
+        )
+        .unwrap();
+        append(
+            &replay_path,
+            &DeadLetter::new(target, "exited with code 1", vec![failing]),
+        )
+        .unwrap();
+        append_line(&replay_path, "not json").unwrap();
+
+        assert!(replay(&settings).is_err());
+        let content = fs::read_to_string(&replay_path).unwrap();
+        let lines = content.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].contains("some-id-error"));
+        assert_eq!(lines[1], "not json");
+        fs::remove_dir_all(&path).unwrap();
+        fs::remove_file(&replay_path).unwrap();
+    }
+}
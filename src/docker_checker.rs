@@ -1,14 +1,76 @@
-use super::config::Config;
-use dockworker::{container::Container, container::ContainerFilters, Docker};
+use super::config::{ApplyTo, Config, DockerConfig};
+use chrono::{DateTime, Utc};
+use dockworker::{container::Container, container::ContainerFilters, container::HealthState, Docker};
+use hooks::{self, Hook};
+use humantime;
+use label_filters::LabelFilters;
+use metrics::{self, ContainerSnapshot, METRICS};
+use notifiers::{Dispatcher, Event, EventKind};
+use policy::{Policy, PolicyRef};
 use regex::Regex;
+use reload::Reloader;
 use std::cell::RefCell;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::rc::Rc;
 use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
 use std::sync::Arc;
-use std::thread;
+use std::thread::{self, JoinHandle};
 use std::time::{Duration, Instant};
 
+// Container events (`Action` field of the docker event) that could change the health of a watched container
+const WATCHED_EVENTS: [&str; 5] = ["health_status", "die", "start", "destroy", "oom"];
+// How long to wait before re-subscribing to the event stream after it was dropped
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+// Upper bound for blocking on the events channel, so the finished flag is still checked regularly
+const EVENT_WAIT: Duration = Duration::from_secs(1);
+
+#[derive(Debug)]
+pub(crate) struct ContainerEvent {
+    pub id: String,
+    pub action: String,
+}
+
+/// Subscription to the docker events of one endpoint, read by a separate thread
+struct EventStream {
+    rx: Receiver<ContainerEvent>,
+    endpoint: String,
+    tls: bool,
+    stop: Arc<AtomicBool>,
+}
+
+impl EventStream {
+    fn is_for(&self, config: &DockerConfig) -> bool {
+        self.endpoint == config.endpoint && self.tls == config.tls
+    }
+}
+
+impl Drop for EventStream {
+    // the thread can be blocked reading the stream, so it isn't joined: it exits on its next event
+    // or reconnect attempt instead of reconnecting to the old endpoint forever
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Last seen health of the container
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Health {
+    Starting,
+    Healthy,
+    Unhealthy,
+}
+
+impl<'a> From<&'a HealthState> for Health {
+    fn from(state: &HealthState) -> Self {
+        match *state {
+            HealthState::Healthy => Health::Healthy,
+            HealthState::Unhealthy => Health::Unhealthy,
+            _ => Health::Starting,
+        }
+    }
+}
+
 #[derive(Default, Debug)]
 pub struct ContainerStats {
     // (4294967295 * 2) / 60 / 60 / 24 / 365
@@ -17,44 +79,349 @@ pub struct ContainerStats {
     pub restarts: u32,
     pub consecutive_failures: u16,
     pub not_seen_since: Option<Instant>,
+    pub health: Option<Health>,
+    // restarts and hooks that were skipped because of dry_run
+    pub dry_run_restarts: u32,
+    pub dry_run_hooks: u32,
+    pub name: String,
+    pub image: String,
+    // compose service name, empty if the container isn't managed by compose
+    pub service: String,
+    // from the effective policy of the container
+    pub hard_failures: u16,
+    pub critical: bool,
+    pub last_restart: Option<DateTime<Utc>>,
+    // restarted and not healthy since then
+    pub recovering: bool,
+    // reached hard_failures and not healthy since then
+    pub hard_failed: bool,
+    // when the last failed check was counted
+    pub last_failure: Option<Instant>,
+    // kept from the last check, the container is gone when the hook runs
+    pub on_disappeared: Option<Hook>,
+}
+
+/// What a failed check of the container leads to
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Failure {
+    // consecutive_failures isn't reached yet
+    Counted,
+    Restart,
+    // the restart that reached hard_failures
+    HardFailure,
+}
+
+impl ContainerStats {
+    /// Counts a failed check against the thresholds of the policy. In dry-run only `dry_run_restarts`
+    /// is counted, so the restart state (`restarts`, `last_restart`, `recovering`, `hard_failed`) stays untouched.
+    pub fn register_failure(&mut self, policy: &Policy, dry_run: bool) -> Failure {
+        self.last_failure = Some(Instant::now());
+        self.consecutive_failures = self.consecutive_failures.saturating_add(1);
+        // not an exact match: the threshold may have been lowered by a reload since the count started
+        if self.consecutive_failures <= policy.consecutive_failures {
+            return Failure::Counted;
+        }
+        self.consecutive_failures = 0;
+        let restarts = if dry_run {
+            self.dry_run_restarts += 1;
+            self.dry_run_restarts
+        } else {
+            self.restarts += 1;
+            self.last_restart = Some(Utc::now());
+            self.recovering = true;
+            self.restarts
+        };
+        if restarts < u32::from(policy.hard_failures) {
+            return Failure::Restart;
+        }
+        if !dry_run {
+            self.hard_failed = true;
+        }
+        Failure::HardFailure
+    }
+
+    /// Failures are counted at most once per `interval`: in the events mode the container is checked
+    /// on every event (health_status, die, ...) and on reconcile, which can come right after each other
+    pub fn failure_due(&self, interval: Duration) -> bool {
+        self.last_failure.map_or(true, |last| last.elapsed() >= interval)
+    }
+}
+
+/// Events of the latest second seen on the stream. Docker sends the events of the `since` second
+/// again when the stream is resubscribed, these are skipped.
+#[derive(Debug, Default)]
+struct SeenEvents {
+    time: u64,
+    events: HashSet<(String, String)>,
+}
+
+impl SeenEvents {
+    /// False if the event was seen already
+    fn insert(&mut self, time: u64, id: &str, action: &str) -> bool {
+        if time < self.time {
+            return false;
+        }
+        if time > self.time {
+            self.time = time;
+            self.events.clear();
+        }
+        self.events.insert((id.to_string(), action.to_string()))
+    }
+}
+
+/// Checks a single container, fails if its state cannot be read
+pub type Callback = fn(&DockerChecker, &Container, PolicyRef) -> Result<(), String>;
+
+/// Result of a check of all containers
+pub(super) struct Tick {
+    pub checked: HashMap<String, (Container, PolicyRef)>,
+    // containers that couldn't be checked
+    pub errors: usize,
 }
 
 // * Possible optimization: make this hashmap transient (having a entries with activity time, and if they aren't active for $time then purge them from hashmap)
-pub type Stats<'a> = Rc<RefCell<HashMap<String, ContainerStats>>>;
+pub type Stats = Rc<RefCell<HashMap<String, ContainerStats>>>;
 
-pub struct DockerChecker<'a> {
-    is_finished: Arc<AtomicBool>,
-    pub client: Docker,
-    pub stats: Stats<'a>,
-    pub config: &'a Config,
-    self_re: Regex,
+/// Compiled filter of the default containers config or one of the policy groups
+pub(super) struct ContainerFilter {
+    apply_to: ApplyTo,
+    label_filters: LabelFilters,
     filter_by_re: Regex,
+    self_re: Regex,
 }
 
-impl<'a> DockerChecker<'a> {
-    pub fn new(connect_str: &str, finished: Arc<AtomicBool>, config: &'a Config) -> Result<Self, String> {
-        let client = DockerChecker::get_new_client(connect_str)?;
-        let re = Regex::new(&config.containers.filter_by).map_err(|e| e.to_string())?;
-        let self_re = match &config.containers.filter_self {
-            Some(ref self_re_pattern) => self_re_pattern,
+impl ContainerFilter {
+    pub fn new(
+        filter_by: &str,
+        filter_self: &Option<String>,
+        apply_to: &ApplyTo,
+        label_filters: &LabelFilters,
+    ) -> Result<Self, String> {
+        let re = Regex::new(filter_by).map_err(|e| e.to_string())?;
+        let self_re = match filter_self {
+            Some(ref self_re_pattern) => self_re_pattern.as_str(),
             None => r#"^\b$"#, // shouldn't match anything. Shouldn't be too expencive for underlying RE engine
         };
         let self_re = Regex::new(self_re).map_err(|e| e.to_string())?;
+        Ok(Self {
+            apply_to: apply_to.clone(),
+            label_filters: label_filters.clone(),
+            filter_by_re: re,
+            self_re,
+        })
+    }
+
+    /// Opt-outs of the filter: a matching label filter or a `filter_self` match
+    pub fn excludes(&self, i: &Container) -> bool {
+        if self.apply_to.should_filter_labels() {
+            let by_label = match i.Labels {
+                Some(ref map) => map.iter().any(|(name, value)| match self.label_filters.get(name) {
+                    Some(label_re) => label_re.is_match(value),
+                    None => false,
+                }),
+                None => false,
+            };
+            if by_label {
+                return true;
+            }
+        }
+        if self.apply_to.should_filter_names() {
+            i.Names.iter().any(|name| self.self_re.is_match(name))
+        } else if self.apply_to.should_filter_images() {
+            self.self_re.is_match(&i.Image)
+        } else {
+            false
+        }
+    }
+
+    /// Should return true _if container should be passed to the callback_,
+    /// false means container is filtered
+    pub fn matches(&self, i: &Container) -> bool {
+        if self.excludes(i) {
+            return false;
+        }
+        if self.apply_to.should_filter_names() {
+            i.Names.iter().any(|name| self.filter_by_re.is_match(name))
+        } else if self.apply_to.should_filter_images() {
+            self.filter_by_re.is_match(&i.Image)
+        } else {
+            false
+        }
+    }
+}
+
+/// Filters compiled from the config: top-level one and one per policy group (same order as `config.containers.policy`)
+fn compile_filters(config: &Config) -> Result<(ContainerFilter, Vec<ContainerFilter>), String> {
+    let containers = &config.containers;
+    let default_filter = ContainerFilter::new(
+        &containers.filter_by,
+        &containers.filter_self,
+        &containers.apply_filter_to,
+        &containers.label_filters,
+    )?;
+    let mut policy_filters = Vec::new();
+    for group in containers.policy.iter() {
+        let filter = ContainerFilter::new(
+            &group.filter_by,
+            &group.filter_self,
+            &group.apply_filter_to,
+            &group.label_filters,
+        )
+        .map_err(|e| format!("Policy \"{}\": {}", group.name, e))?;
+        policy_filters.push(filter);
+    }
+    Ok((default_filter, policy_filters))
+}
+
+fn connect(config: &DockerConfig) -> Result<Docker, String> {
+    let client = DockerChecker::get_new_client(config)?;
+    info!("Using docker endpoint {}", &config.endpoint);
+    if config.tls_enabled() {
+        check_tls_endpoint(&client, &config.endpoint)?;
+    }
+    Ok(client)
+}
+
+pub struct DockerChecker {
+    is_finished: Arc<AtomicBool>,
+    pub client: Docker,
+    pub stats: Stats,
+    pub config: Arc<Config>,
+    // events of the current tick, delivered after all containers were checked
+    pub dispatcher: RefCell<Dispatcher>,
+    hook_runner: hooks::Runner,
+    // restarts and other actions that run in the background
+    tasks: RefCell<Vec<JoinHandle<()>>>,
+    default_filter: ContainerFilter,
+    policy_filters: Vec<ContainerFilter>,
+    reloader: Option<Reloader>,
+}
+
+impl DockerChecker {
+    pub fn new(finished: Arc<AtomicBool>, config: Arc<Config>) -> Result<Self, String> {
+        let client = connect(&config.docker)?;
+        let (default_filter, policy_filters) = compile_filters(&config)?;
+        let dispatcher = Dispatcher::new(&config.notifiers, &config.dead_letters)?;
         Ok(Self {
             client,
             is_finished: finished,
             stats: Rc::new(RefCell::new(HashMap::new())),
-            config: &config,
-            self_re,
-            filter_by_re: re,
+            dispatcher: RefCell::new(dispatcher),
+            hook_runner: hooks::Runner::new(&config.hooks, &config.dead_letters),
+            tasks: RefCell::new(Vec::new()),
+            config,
+            default_filter,
+            policy_filters,
+            reloader: None,
         })
     }
 
-    pub fn get_new_client(connect_str: &str) -> Result<Docker, String> {
+    /// Config will be reloaded between ticks when the reloader asks for it
+    pub fn with_reloader(mut self, reloader: Reloader) -> Self {
+        self.reloader = Some(reloader);
+        self
+    }
+
+    /// Swaps the config. Nothing is changed if the new config can't be applied.
+    /// Stats are kept only for the containers that still pass the new filters.
+    pub fn reload(&mut self, config: Arc<Config>) -> Result<(), String> {
+        let (default_filter, policy_filters) = compile_filters(&config)?;
+        let dispatcher = Dispatcher::new(&config.notifiers, &config.dead_letters)?;
+        if config.docker.endpoint != self.config.docker.endpoint || config.docker.tls != self.config.docker.tls {
+            self.client = connect(&config.docker)?;
+        }
+        if config.docker.use_events != self.config.docker.use_events {
+            warn!("Changing docker.use_events requires a restart, keeping the current mode");
+        }
+        self.default_filter = default_filter;
+        self.policy_filters = policy_filters;
+        self.dispatcher.borrow_mut().flush();
+        self.dispatcher = RefCell::new(dispatcher);
+        // hooks that are already queued or running are kept
+        self.hook_runner.reconfigure(&config.hooks, &config.dead_letters);
+        self.config = config;
+
+        let filter = ContainerFilters::new();
+        match self.client.list_containers(None, None, None, filter) {
+            Ok(containers) => {
+                let matching = containers
+                    .iter()
+                    .filter(|c| self.filter_containers(c).is_some())
+                    .map(|c| c.Id.clone())
+                    .collect::<Vec<_>>();
+                self.stats.borrow_mut().retain(|id, _| matching.contains(id));
+            }
+            // stats of containers that don't match anymore will be purged after `purge_unseen`
+            Err(e) => error!("Error listing containers: {}", e),
+        }
+        Ok(())
+    }
+
+    /// Applies a new config if the reloader has one, returns true if the config was swapped
+    fn maybe_reload(&mut self) -> bool {
+        let new_config = match self.reloader.as_mut().and_then(|reloader| reloader.poll()) {
+            Some(new_config) => new_config,
+            None => return false,
+        };
+        let result = new_config.and_then(|config| self.reload(Arc::new(config)));
+        match result {
+            Ok(()) => {
+                info!("Config reloaded");
+                true
+            }
+            Err(e) => {
+                error!("Cannot reload config, keeping the old one. Error: {}", e);
+                false
+            }
+        }
+    }
+
+    pub fn get_new_client(config: &DockerConfig) -> Result<Docker, String> {
+        let connect_str = config.endpoint.as_str();
+        let tls = config.tls_enabled();
         let client;
-        if connect_str.starts_with("http") {
+        if connect_str.starts_with("https") && !tls {
+            return Err(format!(
+                "{} is a TLS endpoint, but TLS is disabled. Set docker.tls = true (or DOCKER_TLS_VERIFY=1)",
+                connect_str
+            ));
+        }
+        if connect_str.starts_with("https") || (tls && connect_str.starts_with("tcp")) {
+            let files = config.tls_files();
+            let (cert, key) = match (files.cert, files.key) {
+                (Some(cert), Some(key)) => (cert, key),
+                (None, None) => {
+                    // dockworker can only connect with a client identity
+                    return Err(format!(
+                        "TLS is enabled for {}, but there is no client certificate and key: \
+                         server-only TLS isn't supported by the docker client yet, set docker.tls_cert and docker.tls_key",
+                        connect_str
+                    ));
+                }
+                _ => return Err("docker.tls_cert and docker.tls_key must be set together".to_string()),
+            };
+            for path in [&files.ca, &cert, &key].iter() {
+                if !path.is_file() {
+                    return Err(format!(
+                        "TLS is enabled for {}, but {} doesn't exist",
+                        connect_str,
+                        path.display()
+                    ));
+                }
+            }
+            client = Docker::connect_with_ssl(connect_str, &key, &cert, &files.ca).map_err(|e| e.to_string())?;
+        } else if connect_str.starts_with("http") || connect_str.starts_with("tcp") {
+            if tls {
+                return Err(format!(
+                    "TLS is enabled, but {} is a plaintext endpoint. Use tcp:// or https:// instead",
+                    connect_str
+                ));
+            }
             client = Docker::connect_with_http(connect_str).map_err(|e| e.to_string())?;
         } else if connect_str.starts_with("unix") {
+            if tls {
+                debug!("TLS settings are ignored for the unix socket {}", connect_str);
+            }
             client = Docker::connect_with_unix(connect_str).map_err(|e| e.to_string())?;
         } else {
             return Err(format!(
@@ -65,54 +432,22 @@ impl<'a> DockerChecker<'a> {
         Ok(client)
     }
 
-    pub(super) fn filter_containers(&self, i: &Container) -> bool {
-        let apply_to = &self.config.containers.apply_filter_to;
-        let re = &self.filter_by_re;
-        let self_re = &self.self_re;
-        // filter containers by predicates from config
-        // should return true _if container should be passed to the callback_!
-        // false means container is filtered
-        let mut result = false;
-        if apply_to.should_filter_labels() {
-            result |= match i.Labels {
-                Some(ref map) => map
-                    .iter()
-                    .filter(
-                        |(ref name, ref value)| match self.config.containers.label_filters.get(*name) {
-                            Some(ref label_re) => label_re.is_match(value),
-                            None => false,
-                        },
-                    )
-                    .peekable()
-                    .peek()
-                    .is_some(),
-                None => false,
-            }
+    /// Returns the policy that the container matched, or None if container is filtered.
+    /// The top-level `label_filters` and `filter_self` exclusions apply to the groups as well.
+    /// Policy groups are checked in the config order and the first match wins,
+    /// the top-level containers filter is used when none of them matched.
+    pub(super) fn filter_containers(&self, i: &Container) -> Option<PolicyRef> {
+        if self.default_filter.excludes(i) {
+            return None;
         }
-
-        if result {
-            // label filter returned match - bail out
-            return !result;
+        if let Some(idx) = self.policy_filters.iter().position(|filter| filter.matches(i)) {
+            return Some(PolicyRef::Group(idx));
         }
-        /* @FIXME:/DOCME: This is still working incorrectly/ obscure
-            Firstly, if re is a wildcard pattern ".*", then it would greedy match everything
-            self_re should take a priority and bail out earlier.
-            Otherwise it should be documented somehow and point the user towards labels which, in turn, allows
-            more granular filtering
-        */
-
-        if apply_to.should_filter_names() {
-            result |= i
-                .Names
-                .iter()
-                .filter(|&name| re.is_match(name) || self_re.is_match(name))
-                .peekable()
-                .peek()
-                .is_some();
-        } else if apply_to.should_filter_images() {
-            result |= re.is_match(&i.Image) || self_re.is_match(&i.Image);
+        if self.default_filter.matches(i) {
+            Some(PolicyRef::Default)
+        } else {
+            None
         }
-        result
     }
 
     pub(super) fn retain_old_containers(
@@ -124,11 +459,13 @@ impl<'a> DockerChecker<'a> {
         let result = active_containers.contains(k);
         if !result {
             let not_seen_for = v.not_seen_since.get_or_insert(Instant::now());
-            if Instant::now().duration_since(*not_seen_for) >= Duration::from_secs(self.config.docker.purge_unseen) {
+            if Instant::now().duration_since(*not_seen_for) >= self.config.docker.purge_unseen {
                 warn!(
-                    "Retain container {} from because it hasn't been active for at least {} seconds!",
-                    k, self.config.docker.purge_unseen
+                    "Retain container {} from because it hasn't been active for at least {}!",
+                    k,
+                    humantime::format_duration(self.config.docker.purge_unseen)
                 );
+                self.disappeared(k, v);
             } else {
                 return !result;
             }
@@ -136,32 +473,335 @@ impl<'a> DockerChecker<'a> {
         result
     }
 
-    pub fn watch_for(
-        &mut self,
-        sleep_for: Duration,
-        callback: fn(&DockerChecker, &Container) -> (),
-    ) -> Result<(), String> {
+    fn disappeared(&self, id: &str, stats: &mut ContainerStats) {
+        let hook = stats.on_disappeared.clone();
+        self.emit(Event::from_stats(EventKind::Disappeared, id, stats), hook, stats);
+    }
+
+    /// Runs the hook of the event and queues the event for the notifiers.
+    /// In dry-run both are only logged.
+    pub fn emit(&self, event: Event, hook: Option<Hook>, stats: &mut ContainerStats) {
+        if self.config.containers.dry_run {
+            warn!(
+                "dry_run action=would_notify event={} container={} name={}",
+                event.event.as_str(),
+                event.id,
+                event.name
+            );
+            if let Some(hook) = hook {
+                stats.dry_run_hooks += 1;
+                match hooks::command(&hook.template, &event) {
+                    Ok((cmd, args)) => warn!(
+                        "dry_run action=would_run_hook event={} container={} name={} restarts={} hook=\"{}\" args={:?} total={}",
+                        event.event.as_str(),
+                        event.id,
+                        event.name,
+                        event.restarts,
+                        cmd,
+                        args,
+                        stats.dry_run_hooks
+                    ),
+                    Err(e) => warn!("dry_run action=would_run_hook event={} error=\"{}\"", event.event.as_str(), e),
+                }
+            }
+            return;
+        }
+        if let Some(hook) = hook {
+            self.hook_runner.spawn(&hook, &event);
+        }
+        self.dispatcher.borrow_mut().push(event);
+    }
+
+    /// Runs the action in a separate thread, `shutdown` waits for it
+    pub fn spawn_task<F: FnOnce() + Send + 'static>(&self, task: F) {
+        let mut tasks = self.tasks.borrow_mut();
+        tasks.retain(|task| !task.is_finished());
+        tasks.push(thread::spawn(task));
+    }
+
+    /// Waits for the background actions, the hooks and the notifications to finish
+    pub fn shutdown(self) {
+        for task in self.tasks.into_inner() {
+            if task.join().is_err() {
+                error!("Background task has panicked");
+            }
+        }
+        self.dispatcher.into_inner().shutdown();
+        self.hook_runner.shutdown();
+    }
+
+    /// Lists all containers, calls the callback for every container that passed the filters and
+    /// purges stats of the ones that weren't seen for too long.
+    /// Fails if the containers cannot be listed, the failed callbacks are only counted.
+    pub(super) fn check_all(&self, callback: Callback) -> Result<Tick, String> {
+        let started = Instant::now();
+        let filter = ContainerFilters::new();
+        let containers = self.client.list_containers(None, None, None, filter).map_err(|e| {
+            METRICS.docker_api_error();
+            format!("Error listing containers: {}", e)
+        })?;
         let mut active_containers: Vec<String> = Vec::new();
-        while !self.is_finished.load(Ordering::Relaxed) {
-            active_containers.clear();
-            let filter = ContainerFilters::new();
-            let containers = self
-                .client
-                .list_containers(None, None, None, filter)
-                .map_err(|e| error!("Error listing containers: {}", e))
-                .unwrap_or(Vec::new());
-            containers.iter().filter(|&i| self.filter_containers(i)).for_each(|c| {
+        let mut checked = HashMap::new();
+        let mut errors = 0;
+        for c in containers.into_iter() {
+            if let Some(policy) = self.filter_containers(&c) {
                 active_containers.push(c.Id.clone());
-                trace!("Got container {:?}: calling callback", c);
-                callback(&self, &c);
-            });
-            self.stats
-                .borrow_mut()
-                .retain(|k, v| self.retain_old_containers(&mut active_containers, k, v));
-            thread::sleep(sleep_for);
+                trace!("Got container {:?} ({:?}): calling callback", c, policy);
+                if let Err(e) = callback(&self, &c, policy) {
+                    error!("{}", e);
+                    errors += 1;
+                }
+                checked.insert(c.Id.clone(), (c, policy));
+            }
+        }
+        self.stats
+            .borrow_mut()
+            .retain(|k, v| self.retain_old_containers(&mut active_containers, k, v));
+        self.dispatcher.borrow_mut().flush();
+        METRICS.observe_loop(started.elapsed());
+        self.publish_stats();
+        Ok(Tick { checked, errors })
+    }
+
+    /// Makes the current stats available to the metrics and status endpoints and the textfile,
+    /// after every tick and every handled event
+    pub(super) fn publish_stats(&self) {
+        let snapshots = self
+            .stats
+            .borrow()
+            .iter()
+            .map(|(id, stats)| ContainerSnapshot {
+                id: id.clone(),
+                name: stats.name.clone(),
+                image: stats.image.clone(),
+                service: stats.service.clone(),
+                health: stats.health,
+                count: stats.count,
+                restarts: stats.restarts,
+                consecutive_failures: stats.consecutive_failures,
+                hard_failures: stats.hard_failures,
+                hard_failed: stats.hard_failed,
+                critical: stats.critical,
+                last_restart: stats.last_restart,
+                dry_run_restarts: stats.dry_run_restarts,
+                dry_run_hooks: stats.dry_run_hooks,
+            })
+            .collect();
+        METRICS.set_containers(snapshots);
+        if let Some(ref dir) = self.config.metrics.textfile_dir {
+            if let Err(e) = metrics::write_textfile(&METRICS, dir) {
+                error!("Cannot write metrics to {}: {}", dir, e);
+            }
+        }
+    }
+
+    /// Checks all containers every `containers.poll_interval`
+    pub fn watch_for(&mut self, callback: Callback) -> Result<(), String> {
+        while !self.is_finished.load(Ordering::Relaxed) {
+            self.maybe_reload();
+            if let Err(e) = self.check_all(callback) {
+                error!("{}", e);
+            }
+            thread::sleep(self.config.containers.poll_interval);
+        }
+        Ok(())
+    }
+
+    /// Same as `watch_for`, but driven by the docker events stream.
+    /// The callback is called as soon as an event for a watched container arrives,
+    /// and additionally for every container each `docker.reconcile_interval`, which catches
+    /// everything that was missed while the stream was reconnecting.
+    pub fn watch_events(&mut self, callback: Callback) -> Result<(), String> {
+        let mut stream = self.subscribe_events();
+
+        let mut known = HashMap::new();
+        let mut next_reconcile = Instant::now();
+        while !self.is_finished.load(Ordering::Relaxed) {
+            if self.maybe_reload() {
+                if !stream.is_for(&self.config.docker) {
+                    // the old stream is stopped when it's dropped
+                    stream = self.subscribe_events();
+                }
+                // containers may match other policies now
+                next_reconcile = Instant::now();
+            }
+            let now = Instant::now();
+            if now >= next_reconcile {
+                debug!("Reconciling state of all containers");
+                match self.check_all(callback) {
+                    Ok(tick) => known = tick.checked,
+                    Err(e) => error!("{}", e),
+                }
+                next_reconcile = now + self.config.docker.reconcile_interval;
+                continue;
+            }
+            let wait = std::cmp::min(next_reconcile - now, EVENT_WAIT);
+            match stream.rx.recv_timeout(wait) {
+                Ok(event) => self.handle_event(event, &mut known, callback),
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => {
+                    return Err("Docker events stream has stopped unexpectedly".to_string());
+                }
+            }
         }
         Ok(())
     }
+
+    fn subscribe_events(&self) -> EventStream {
+        let (tx, rx) = mpsc::channel();
+        let docker_config = self.config.docker.clone();
+        let finished = self.is_finished.clone();
+        let stop = Arc::new(AtomicBool::new(false));
+        let stream = EventStream {
+            rx,
+            endpoint: docker_config.endpoint.clone(),
+            tls: docker_config.tls,
+            stop: stop.clone(),
+        };
+        thread::spawn(move || stream_events(&docker_config, finished, stop, tx));
+        stream
+    }
+
+    pub(super) fn handle_event(
+        &self,
+        event: ContainerEvent,
+        known: &mut HashMap<String, (Container, PolicyRef)>,
+        callback: Callback,
+    ) {
+        trace!("Got event {:?}", event);
+        match event.action.as_str() {
+            "destroy" => {
+                known.remove(&event.id);
+                let removed = self.stats.borrow_mut().remove(&event.id);
+                if let Some(mut stats) = removed {
+                    debug!("Container {} was destroyed, dropping its stats", &event.id);
+                    self.disappeared(&event.id, &mut stats);
+                    self.dispatcher.borrow_mut().flush();
+                    self.publish_stats();
+                }
+                return;
+            }
+            "die" | "oom" => {
+                if known.contains_key(&event.id) {
+                    warn!("Container {} got `{}` event", &event.id, &event.action);
+                }
+            }
+            _ => {}
+        }
+        if !known.contains_key(&event.id) {
+            // container we haven't seen yet (e.g. just started) - only refresh the list, counters are updated on reconcile
+            let filter = ContainerFilters::new();
+            match self.client.list_containers(None, None, None, filter) {
+                Ok(containers) => {
+                    if let Some(c) = containers.into_iter().find(|c| c.Id == event.id) {
+                        if let Some(policy) = self.filter_containers(&c) {
+                            known.insert(c.Id.clone(), (c, policy));
+                        }
+                    }
+                }
+                Err(e) => {
+                    METRICS.docker_api_error();
+                    error!("Error listing containers: {}", e)
+                }
+            }
+        }
+        if let Some((c, policy)) = known.get(&event.id) {
+            trace!("Got container {:?} ({:?}): calling callback", c, policy);
+            if let Err(e) = callback(&self, c, *policy) {
+                error!("{}", e);
+            }
+            self.dispatcher.borrow_mut().flush();
+            self.publish_stats();
+        }
+    }
+}
+
+/// TLS misconfiguration is reported only on the first request, so do one right away to fail early
+fn check_tls_endpoint(client: &Docker, connect_str: &str) -> Result<(), String> {
+    if connect_str.starts_with("unix") {
+        return Ok(());
+    }
+    client.ping().map_err(|e| {
+        let e = e.to_string();
+        // that's what openssl says when the other side answers with plain http
+        if e.contains("wrong version number") || e.contains("unknown protocol") || e.contains("http request") {
+            format!(
+                "TLS is enabled, but {} doesn't speak TLS (plaintext endpoint?). Error: {}",
+                connect_str, e
+            )
+        } else {
+            format!("Cannot establish TLS connection to {}. Error: {}", connect_str, e)
+        }
+    })
+}
+
+pub(crate) fn is_watched_event(action: &str) -> bool {
+    // health_status events are reported as "health_status: healthy"
+    WATCHED_EVENTS
+        .iter()
+        .any(|watched| action == *watched || action.starts_with(&format!("{}:", watched)))
+}
+
+/// Runs in a separate thread: forwards container events to the checker and reconnects when the stream drops.
+/// Exits when the checker is finished or the stream is stopped.
+fn stream_events(config: &DockerConfig, finished: Arc<AtomicBool>, stop: Arc<AtomicBool>, tx: Sender<ContainerEvent>) {
+    let connect_uri = &config.endpoint;
+    let stopped = || finished.load(Ordering::Relaxed) || stop.load(Ordering::Relaxed);
+    let mut since = None;
+    let mut seen = SeenEvents::default();
+    while !stopped() {
+        let client = match DockerChecker::get_new_client(config) {
+            Ok(client) => client,
+            Err(e) => {
+                error!("Cannot get the docker client. URI: {}, error: {}", connect_uri, e);
+                thread::sleep(RECONNECT_DELAY);
+                continue;
+            }
+        };
+        match client.events(since, None, None) {
+            Ok(events) => {
+                info!("Subscribed to docker events on {}", connect_uri);
+                for event in events {
+                    let event = match event {
+                        Ok(event) => event,
+                        Err(e) => {
+                            warn!("Error reading docker events: {}", e);
+                            break;
+                        }
+                    };
+                    since = Some(event.time);
+                    if event.Type != "container" || !is_watched_event(&event.Action) {
+                        continue;
+                    }
+                    if !seen.insert(event.time, &event.Actor.ID, &event.Action) {
+                        trace!("Skipping replayed event {} of {}", &event.Action, &event.Actor.ID);
+                        continue;
+                    }
+                    let action = event.Action.splitn(2, ':').next().unwrap_or("").to_string();
+                    if stopped()
+                        || tx
+                            .send(ContainerEvent {
+                                id: event.Actor.ID,
+                                action,
+                            })
+                            .is_err()
+                    {
+                        // checker is gone or has subscribed again, nothing to do anymore
+                        return;
+                    }
+                }
+            }
+            Err(e) => error!("Cannot subscribe to docker events. URI: {}, error: {}", connect_uri, e),
+        }
+        if stopped() {
+            break;
+        }
+        warn!(
+            "Docker events stream disconnected, reconnecting in {} seconds",
+            RECONNECT_DELAY.as_secs()
+        );
+        thread::sleep(RECONNECT_DELAY);
+    }
 }
 
 #[cfg(test)]
@@ -169,6 +809,7 @@ mod tests {
     use super::*;
     use config;
     use dockworker::container::{Container, HostConfig, Port};
+    use policy::CONSECUTIVE_FAILURES_LABEL;
 
     fn create_mock_container(
         name: Option<String>,
@@ -196,17 +837,19 @@ mod tests {
     fn filter_containers_test() {
         let settings = config::get_settings("tests/settings").unwrap();
         let finished = Arc::new(AtomicBool::new(false));
-        let dc = DockerChecker::new(&settings.docker.connect_uri, finished, &settings).unwrap();
+        let dc = DockerChecker::new(finished, Arc::new(settings)).unwrap();
 
         // filter by name
-        assert!(
+        assert_eq!(
             dc.filter_containers(&create_mock_container(None, Some("filter_me".to_string()), None)),
+            Some(PolicyRef::Default),
             "Should not be filtered!"
         );
 
         // filter by image
         assert!(
-            dc.filter_containers(&create_mock_container(Some("Random-image-id".to_string()), None, None)),
+            dc.filter_containers(&create_mock_container(Some("Random-image-id".to_string()), None, None))
+                .is_some(),
             "Should not be filtered!"
         );
 
@@ -215,8 +858,130 @@ mod tests {
         map.entry("im.lain.docker-check".to_string())
             .or_insert("skipme".to_string());
         assert!(
-            !dc.filter_containers(&create_mock_container(None, Some("filter_me".to_string()), Some(map))),
+            dc.filter_containers(&create_mock_container(None, Some("filter_me".to_string()), Some(map)))
+                .is_none(),
             "Should be filtered by label!"
         );
     }
+
+    #[test]
+    fn filter_containers_policy_groups_test() {
+        let settings = config::get_settings("tests/settings").unwrap();
+        let finished = Arc::new(AtomicBool::new(false));
+        let dc = DockerChecker::new(finished, Arc::new(settings)).unwrap();
+
+        assert_eq!(
+            dc.filter_containers(&create_mock_container(None, Some("postgres:11".to_string()), None)),
+            Some(PolicyRef::Group(0)),
+            "Should match the databases policy"
+        );
+        assert_eq!(
+            dc.filter_containers(&create_mock_container(Some("/web_1".to_string()), None, None)),
+            Some(PolicyRef::Default),
+            "Should fall back to the default policy"
+        );
+
+        // global opt-outs win over the groups
+        let mut map = HashMap::new();
+        map.insert("im.lain.docker-check".to_string(), "skipme".to_string());
+        assert_eq!(
+            dc.filter_containers(&create_mock_container(None, Some("postgres:11".to_string()), Some(map))),
+            None,
+            "Should be filtered by the global label filter"
+        );
+        assert_eq!(
+            dc.filter_containers(&create_mock_container(
+                Some("/skipme".to_string()),
+                Some("postgres:11".to_string()),
+                None
+            )),
+            None,
+            "Should be filtered by filter_self"
+        );
+    }
+
+    fn failures(stats: &mut ContainerStats, policy: &Policy, dry_run: bool, checks: usize) -> Vec<Failure> {
+        (0..checks).map(|_| stats.register_failure(policy, dry_run)).collect()
+    }
+
+    #[test]
+    fn register_failure_test() {
+        let mut policy = Policy::from_config(&config::get_settings("tests/settings").unwrap());
+        policy.consecutive_failures = 1;
+        policy.hard_failures = 2;
+        let expected = vec![
+            Failure::Counted,
+            Failure::Restart,
+            Failure::Counted,
+            Failure::HardFailure,
+        ];
+
+        let mut stats = ContainerStats::default();
+        assert_eq!(failures(&mut stats, &policy, false, 4), expected);
+        assert_eq!(stats.restarts, 2);
+        assert!(stats.last_restart.is_some());
+        assert!(stats.recovering);
+        assert!(stats.hard_failed);
+        assert_eq!(stats.dry_run_restarts, 0);
+
+        // the dry run only counts the restarts it would make
+        let mut stats = ContainerStats::default();
+        assert_eq!(failures(&mut stats, &policy, true, 4), expected);
+        assert_eq!(stats.restarts, 0);
+        assert_eq!(stats.last_restart, None);
+        assert!(!stats.recovering);
+        assert!(!stats.hard_failed);
+        assert_eq!(stats.dry_run_restarts, 2);
+        assert_eq!(stats.consecutive_failures, 0);
+
+        // a reload lowered the threshold below the running count
+        let mut stats = ContainerStats::default();
+        policy.consecutive_failures = 5;
+        assert_eq!(failures(&mut stats, &policy, false, 4), vec![Failure::Counted; 4]);
+        policy.consecutive_failures = 2;
+        assert_eq!(stats.register_failure(&policy, false), Failure::Restart);
+        assert_eq!(stats.consecutive_failures, 0);
+
+        // relabelled mid-count with a lower docker-check.consecutive_failures
+        let settings = config::get_settings("tests/settings").unwrap();
+        let mut stats = ContainerStats::default();
+        let global = Policy::resolve(&settings, PolicyRef::Default, "dfdb8ee577c1", None);
+        assert_eq!(failures(&mut stats, &global, false, 3), vec![Failure::Counted; 3]);
+        let mut labels = HashMap::new();
+        labels.insert(CONSECUTIVE_FAILURES_LABEL.to_string(), "1".to_string());
+        let labelled = Policy::resolve(&settings, PolicyRef::Default, "dfdb8ee577c1", Some(&labels));
+        assert_eq!(labelled.consecutive_failures, 1);
+        assert_eq!(stats.register_failure(&labelled, false), Failure::Restart);
+    }
+
+    #[test]
+    fn failure_due_test() {
+        let policy = Policy::from_config(&config::get_settings("tests/settings").unwrap());
+        let mut stats = ContainerStats::default();
+        assert!(stats.failure_due(Duration::from_secs(2)));
+        stats.register_failure(&policy, false);
+        assert!(!stats.failure_due(Duration::from_secs(2)));
+        assert!(stats.failure_due(Duration::from_secs(0)));
+    }
+
+    #[test]
+    fn seen_events_test() {
+        let mut seen = SeenEvents::default();
+        assert!(seen.insert(100, "dfdb8ee577c1", "die"));
+        assert!(seen.insert(100, "dfdb8ee577c1", "start"));
+        assert!(seen.insert(100, "ce94baa47eed", "die"));
+        // resubscribed with since=100
+        assert!(!seen.insert(100, "dfdb8ee577c1", "die"));
+        assert!(seen.insert(101, "dfdb8ee577c1", "die"));
+        assert!(!seen.insert(100, "ce94baa47eed", "start"));
+    }
+
+    #[test]
+    fn is_watched_event_test() {
+        assert!(is_watched_event("health_status: unhealthy"));
+        assert!(is_watched_event("die"));
+        assert!(is_watched_event("destroy"));
+        assert!(!is_watched_event("exec_start: /bin/sh -c curl localhost"));
+        assert!(!is_watched_event("health_statuses"));
+    }
 }
@@ -0,0 +1,154 @@
+/* Resolves the docker endpoint the same way the docker cli does when `connect_uri` isn't set:
+    DOCKER_HOST, then the current context (DOCKER_CONTEXT or `currentContext` from ~/.docker/config.json),
+    then the rootless socket in $XDG_RUNTIME_DIR and finally the default unix socket.
+*/
+use serde_json;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+pub const DEFAULT_SOCKET: &str = "unix:///var/run/docker.sock";
+const DEFAULT_CONTEXT: &str = "default";
+
+#[derive(Debug, PartialEq)]
+pub struct Endpoint {
+    pub uri: String,
+    // directory with the TLS material of the docker context, if it has one
+    pub tls_dir: Option<PathBuf>,
+}
+
+impl Endpoint {
+    fn plain(uri: String) -> Self {
+        Self { uri, tls_dir: None }
+    }
+}
+
+#[derive(Deserialize)]
+struct CliConfig {
+    #[serde(rename = "currentContext")]
+    current_context: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ContextMeta {
+    #[serde(rename = "Endpoints")]
+    endpoints: HashMap<String, ContextEndpoint>,
+}
+
+#[derive(Deserialize)]
+struct ContextEndpoint {
+    #[serde(rename = "Host")]
+    host: Option<String>,
+}
+
+pub fn resolve() -> Result<Endpoint, String> {
+    resolve_with(&|name| std::env::var(name).ok().filter(|v| !v.is_empty()))
+}
+
+pub(crate) fn resolve_with(env: &dyn Fn(&str) -> Option<String>) -> Result<Endpoint, String> {
+    if let Some(host) = env("DOCKER_HOST") {
+        return Ok(Endpoint::plain(host));
+    }
+    let config_dir = match env("DOCKER_CONFIG") {
+        Some(dir) => Some(PathBuf::from(dir)),
+        None => env("HOME").map(|home| Path::new(&home).join(".docker")),
+    };
+    if let Some(config_dir) = config_dir {
+        let context = match env("DOCKER_CONTEXT") {
+            Some(context) => Some(context),
+            None => current_context(&config_dir)?,
+        };
+        match context {
+            Some(ref name) if name != DEFAULT_CONTEXT => return context_endpoint(&config_dir, name),
+            _ => {}
+        }
+    }
+    if let Some(runtime_dir) = env("XDG_RUNTIME_DIR") {
+        let rootless = Path::new(&runtime_dir).join("docker.sock");
+        if rootless.exists() {
+            return Ok(Endpoint::plain(format!("unix://{}", rootless.display())));
+        }
+    }
+    Ok(Endpoint::plain(DEFAULT_SOCKET.to_string()))
+}
+
+fn current_context(config_dir: &Path) -> Result<Option<String>, String> {
+    let path = config_dir.join("config.json");
+    if !path.is_file() {
+        return Ok(None);
+    }
+    let file = File::open(&path).map_err(|e| format!("Cannot read {}: {}", path.display(), e))?;
+    let config: CliConfig =
+        serde_json::from_reader(file).map_err(|e| format!("Cannot parse {}: {}", path.display(), e))?;
+    Ok(config.current_context)
+}
+
+fn context_endpoint(config_dir: &Path, name: &str) -> Result<Endpoint, String> {
+    // contexts are stored in directories named by sha256 of the context name
+    let id = format!("{:x}", Sha256::digest(name.as_bytes()));
+    let path = config_dir.join("contexts").join("meta").join(&id).join("meta.json");
+    let file = File::open(&path)
+        .map_err(|e| format!("Cannot read docker context \"{}\" ({}): {}", name, path.display(), e))?;
+    let meta: ContextMeta =
+        serde_json::from_reader(file).map_err(|e| format!("Cannot parse {}: {}", path.display(), e))?;
+    let host = meta
+        .endpoints
+        .get("docker")
+        .and_then(|endpoint| endpoint.host.clone())
+        .ok_or_else(|| format!("Docker context \"{}\" doesn't have a docker endpoint", name))?;
+    let tls_dir = config_dir.join("contexts").join("tls").join(&id).join("docker");
+    Ok(Endpoint {
+        uri: host,
+        tls_dir: if tls_dir.is_dir() { Some(tls_dir) } else { None },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn env_from(vars: Vec<(&'static str, &'static str)>) -> impl Fn(&str) -> Option<String> {
+        move |name| vars.iter().find(|(k, _)| *k == name).map(|(_, v)| v.to_string())
+    }
+
+    #[test]
+    fn docker_host_takes_priority() {
+        let env = env_from(vec![
+            ("DOCKER_HOST", "tcp://docker:2375"),
+            ("DOCKER_CONFIG", "tests/docker-config"),
+        ]);
+        assert_eq!(
+            resolve_with(&env).unwrap(),
+            Endpoint::plain("tcp://docker:2375".to_string())
+        );
+    }
+
+    #[test]
+    fn current_context_is_used() {
+        let env = env_from(vec![("DOCKER_CONFIG", "tests/docker-config")]);
+        let endpoint = resolve_with(&env).unwrap();
+        assert_eq!(endpoint.uri, "tcp://10.0.0.5:2376");
+        assert!(endpoint.tls_dir.unwrap().ends_with("docker"));
+    }
+
+    #[test]
+    fn default_context_falls_through() {
+        let env = env_from(vec![
+            ("DOCKER_CONFIG", "tests/docker-config"),
+            ("DOCKER_CONTEXT", "default"),
+        ]);
+        assert_eq!(resolve_with(&env).unwrap().uri, DEFAULT_SOCKET);
+        let env = env_from(vec![
+            ("DOCKER_CONTEXT", "missing"),
+            ("DOCKER_CONFIG", "tests/docker-config"),
+        ]);
+        assert!(resolve_with(&env).is_err());
+    }
+
+    #[test]
+    fn rootless_socket_is_detected() {
+        let env = env_from(vec![("HOME", "/nonexistent"), ("XDG_RUNTIME_DIR", "tests/rootless")]);
+        assert_eq!(resolve_with(&env).unwrap().uri, "unix://tests/rootless/docker.sock");
+    }
+}
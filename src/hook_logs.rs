@@ -0,0 +1,287 @@
+/* Output of the hooks: every hook execution gets an ID, `<timestamp>-<container>-<event>`, and its merged
+    stdout and stderr go to `<[hooks] log_dir>/<id>.log`, all the attempts of the execution to the same file.
+    The output of an attempt is cut to its last `max_log_size` bytes, only the `keep_logs` newest files are kept.
+    Files with other names are never removed, so `log_dir` may be shared.
+    The main log only gets a single line with the ID and the path of the file.
+*/
+use chrono::{DateTime, Utc};
+use config::HookRunnerConfig;
+use notifiers::{Event, EventKind};
+use regex::Regex;
+use run_command::CommandResult;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+const EXTENSION: &str = "log";
+
+lazy_static! {
+    // names of the files written by `HookLogs::create`, nothing else in the directory is touched
+    static ref LOG_NAME: Regex = Regex::new(&format!(
+        r"^\d{{8}}T\d{{6}}\.\d{{3}}Z-[A-Za-z0-9_.-]+-({})(-\d+)?\.{}$",
+        EventKind::ALL.iter().map(|kind| kind.as_str()).collect::<Vec<_>>().join("|"),
+        EXTENSION
+    ))
+    .unwrap();
+}
+
+/// ID of a hook execution, safe to be used as a file name
+pub fn execution_id(event: &Event, time: DateTime<Utc>) -> String {
+    let container = event
+        .name
+        .trim_start_matches('/')
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '.' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect::<String>();
+    let container = if container.is_empty() {
+        event.id.chars().take(12).collect()
+    } else {
+        container
+    };
+    format!(
+        "{}-{}-{}",
+        time.format("%Y%m%dT%H%M%S%.3fZ"),
+        container,
+        event.event.as_str()
+    )
+}
+
+/// The last `max_size` bytes of the output, with a note of how much was cut
+fn truncate(output: &str, max_size: usize) -> String {
+    if output.len() <= max_size {
+        return output.to_string();
+    }
+    let mut start = output.len() - max_size;
+    while !output.is_char_boundary(start) {
+        start += 1;
+    }
+    format!("[{} bytes truncated]\n{}", start, &output[start..])
+}
+
+fn is_hook_log(path: &Path) -> bool {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .map_or(false, |name| LOG_NAME.is_match(name))
+}
+
+#[derive(Debug, Clone)]
+pub struct HookLogs {
+    dir: PathBuf,
+    max_size: usize,
+    keep: usize,
+}
+
+impl HookLogs {
+    pub fn new(config: &HookRunnerConfig) -> Self {
+        Self {
+            dir: PathBuf::from(&config.log_dir),
+            max_size: config.max_log_size,
+            keep: config.keep_logs,
+        }
+    }
+
+    /// Creates the log file of the execution and removes the oldest ones.
+    /// Returns the ID, with a suffix if another execution already has it, and the path of the file.
+    pub fn create(&self, id: &str) -> Result<(String, PathBuf), String> {
+        fs::create_dir_all(&self.dir).map_err(|e| format!("Cannot create {}: {}", self.dir.display(), e))?;
+        let mut suffix = 0;
+        loop {
+            let unique_id = if suffix == 0 {
+                id.to_string()
+            } else {
+                format!("{}-{}", id, suffix)
+            };
+            let path = self.dir.join(format!("{}.{}", unique_id, EXTENSION));
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(_) => {
+                    self.prune(&path);
+                    return Ok((unique_id, path));
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists => suffix += 1,
+                Err(e) => return Err(format!("Cannot create {}: {}", path.display(), e)),
+            }
+        }
+    }
+
+    /// Writes the command, the output and the result of an attempt
+    pub fn append(
+        &self,
+        path: &Path,
+        attempt: u32,
+        cmd: &str,
+        args: &[String],
+        result: &CommandResult,
+        elapsed: Duration,
+    ) -> Result<(), String> {
+        let (output, status) = match result {
+            Ok(output) => (output.output.as_str(), "succeeded".to_string()),
+            Err(e) => (e.output(), e.to_string()),
+        };
+        let mut file = OpenOptions::new()
+            .append(true)
+            .open(path)
+            .map_err(|e| format!("Cannot open {}: {}", path.display(), e))?;
+        let mut output = truncate(output, self.max_size);
+        if !output.is_empty() && !output.ends_with('\n') {
+            output.push('\n');
+        }
+        writeln!(
+            file,
+            "# attempt {} at {}: {} {}\n{}# {} after {:?}",
+            attempt,
+            Utc::now().to_rfc3339(),
+            cmd,
+            args.join(" "),
+            output,
+            status,
+            elapsed
+        )
+        .map_err(|e| format!("Cannot write to {}: {}", path.display(), e))
+    }
+
+    /// Keeps the `keep` newest logs including the `current` one,
+    /// the IDs start with the time so the names sort chronologically
+    fn prune(&self, current: &Path) {
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) => {
+                warn!("Cannot list {}: {}", self.dir.display(), e);
+                return;
+            }
+        };
+        let mut logs = entries
+            .filter_map(Result::ok)
+            .map(|entry| entry.path())
+            .filter(|path| path != current && path.is_file() && is_hook_log(path))
+            .collect::<Vec<_>>();
+        let keep = self.keep.saturating_sub(1);
+        if logs.len() <= keep {
+            return;
+        }
+        logs.sort();
+        let excess = logs.len() - keep;
+        for path in logs.into_iter().take(excess) {
+            // another worker may have removed it already
+            if let Err(e) = fs::remove_file(&path) {
+                debug!("Cannot remove {}: {}", path.display(), e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use notifiers::tests::event;
+    use run_command::HookError;
+    use std::env;
+    use std::process;
+
+    fn logs(name: &str, keep: usize) -> HookLogs {
+        let dir = env::temp_dir().join(format!("docker-check-{}-{}", name, process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        HookLogs {
+            dir,
+            max_size: 16,
+            keep,
+        }
+    }
+
+    #[test]
+    fn execution_id_test() {
+        let time = Utc.ymd(2019, 1, 1).and_hms_milli(12, 30, 0, 42);
+        assert_eq!(execution_id(&event(), time), "20190101T123000.042Z-db-hard_failure");
+        let mut event = event();
+        event.name = "/web/1 (old)".to_string();
+        assert_eq!(
+            execution_id(&event, time),
+            "20190101T123000.042Z-web_1__old_-hard_failure"
+        );
+        event.name = "/".to_string();
+        assert_eq!(execution_id(&event, time), "20190101T123000.042Z-0123abcd-hard_failure");
+    }
+
+    #[test]
+    fn truncate_test() {
+        assert_eq!(truncate("short", 16), "short");
+        assert_eq!(truncate("0123456789", 4), "[6 bytes truncated]\n6789");
+        // never splits a character
+        assert_eq!(truncate("café", 1), "[5 bytes truncated]\n");
+    }
+
+    #[test]
+    fn attempts_are_appended() {
+        let logs = logs("hook-logs-append", 10);
+        let (id, path) = logs.create("20190101T000000.000Z-db-restart").unwrap();
+        assert_eq!(id, "20190101T000000.000Z-db-restart");
+        let failed: CommandResult = Err(HookError::ExitCode {
+            code: 1,
+            output: "timeout\ndb unreachable!\n".to_string(),
+        });
+        let args = vec!["db".to_string()];
+        logs.append(&path, 1, "notify.sh", &args, &failed, Duration::from_millis(5))
+            .unwrap();
+        let timed_out: CommandResult = Err(HookError::TimedOut(Duration::from_secs(1)));
+        logs.append(&path, 2, "notify.sh", &args, &timed_out, Duration::from_secs(1))
+            .unwrap();
+
+        let content = fs::read_to_string(&path).unwrap();
+        let lines = content.lines().collect::<Vec<_>>();
+        assert!(lines[0].starts_with("# attempt 1 at "));
+        assert!(lines[0].ends_with(": notify.sh db"));
+        assert_eq!(lines[1], "[8 bytes truncated]");
+        assert_eq!(lines[2], "db unreachable!");
+        assert_eq!(lines[3], "# exited with code 1 after 5ms");
+        assert!(lines[4].starts_with("# attempt 2 at "));
+        assert!(lines[5].starts_with("# timed out after 1s"));
+        assert_eq!(lines.len(), 6);
+        fs::remove_dir_all(&logs.dir).unwrap();
+    }
+
+    #[test]
+    fn oldest_logs_are_removed() {
+        let logs = logs("hook-logs-prune", 2);
+        // files that weren't written by the hooks are never removed
+        fs::create_dir_all(&logs.dir).unwrap();
+        for name in [
+            "00000000T000000.000Z-syslog.log",
+            "app.log",
+            "20190101T000000.000Z-db-backup.log",
+        ]
+        .iter()
+        {
+            fs::write(logs.dir.join(name), "").unwrap();
+        }
+        for id in ["20190101T000003.000Z-db-restart", "20190101T000001.000Z-db-restart"].iter() {
+            logs.create(id).unwrap();
+        }
+        let (id, _) = logs.create("20190101T000001.000Z-db-restart").unwrap();
+        assert_eq!(id, "20190101T000001.000Z-db-restart-1");
+        logs.create("20190101T000004.000Z-db-restart").unwrap();
+
+        let mut names = fs::read_dir(&logs.dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect::<Vec<_>>();
+        names.sort();
+        assert_eq!(
+            names,
+            vec![
+                "00000000T000000.000Z-syslog.log",
+                "20190101T000000.000Z-db-backup.log",
+                "20190101T000003.000Z-db-restart.log",
+                "20190101T000004.000Z-db-restart.log",
+                "app.log"
+            ]
+        );
+        fs::remove_dir_all(&logs.dir).unwrap();
+    }
+}
@@ -0,0 +1,485 @@
+/* Lifecycle hooks: external commands that are run on container events,
+    `[containers.hooks]`, `[containers.policy.hooks]` or `docker-check.on_<event>` labels.
+    `run_on_failure` is the hook of the hard_failure event unless `on_hard_failure` is set.
+
+    A hook is an argv template split like a shell would do it (quotes work, no variables or pipes),
+    with the placeholders {id}, {name}, {image}, {restarts}, {event} and {host}. A template without
+    placeholders gets the container id as the only argument, e.g. "notify.sh" runs as "notify.sh $id".
+    The same values are exported as DOCKER_CHECK_ID, DOCKER_CHECK_NAME, ..., and the whole event
+    is written to the stdin of the hook as JSON.
+
+    Hooks are run by a pool of `[hooks] max_concurrent` threads, the rest wait in a queue of `queue_size`
+    and are dropped when it's full. A config reload only changes these limits, queued hooks are kept.
+    A hook is killed with its whole process group after `timeout`.
+    A failed hook is run again `retries` times, unless it cannot be started at all, and then
+    goes to the dead-letter log. The output of the hooks is written to separate files, see hook_logs.rs.
+*/
+use chrono::Utc;
+use config::{DeadLettersConfig, HookRunnerConfig};
+use dead_letters::{self, DeadLetter, Target};
+use hook_logs::{self, HookLogs};
+use metrics::METRICS;
+use notifiers::Event;
+use regex::{Captures, Regex};
+use retry::Retry;
+use run_command::{self, HookError};
+use serde_json;
+use shell_words;
+use std::collections::VecDeque;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+const PLACEHOLDERS: [&str; 6] = ["id", "name", "image", "restarts", "event", "host"];
+const ENV_PREFIX: &str = "DOCKER_CHECK_";
+
+lazy_static! {
+    static ref PLACEHOLDER: Regex = Regex::new(r"\{([a-z_]*)\}").unwrap();
+}
+
+/// Splits the template into words and checks the placeholders, the first word is the command
+pub fn parse(template: &str) -> Result<Vec<String>, String> {
+    let words = shell_words::split(template).map_err(|e| format!("hook \"{}\" cannot be parsed: {}", template, e))?;
+    if words.is_empty() {
+        return Err("hook path is empty".to_string());
+    }
+    for captures in PLACEHOLDER.captures_iter(template) {
+        if !PLACEHOLDERS.contains(&&captures[1]) {
+            return Err(format!(
+                "hook \"{}\" has unknown placeholder {}, expected one of: {{{}}}",
+                template,
+                &captures[0],
+                PLACEHOLDERS.join("}, {")
+            ));
+        }
+    }
+    Ok(words)
+}
+
+fn value(event: &Event, placeholder: &str) -> String {
+    match placeholder {
+        "id" => event.id.clone(),
+        "name" => event.name.trim_start_matches('/').to_string(),
+        "image" => event.image.clone(),
+        "restarts" => event.restarts.to_string(),
+        "event" => event.event.as_str().to_string(),
+        "host" => event.host.clone(),
+        _ => String::new(),
+    }
+}
+
+/// Command and its arguments with the placeholders replaced by the values of the event
+pub fn command(template: &str, event: &Event) -> Result<(String, Vec<String>), String> {
+    let mut words = parse(template)?;
+    if !PLACEHOLDER.is_match(template) {
+        words.push(event.id.clone());
+    }
+    let mut words = words
+        .iter()
+        .map(|word| PLACEHOLDER.replace_all(word, |captures: &Captures| value(event, &captures[1])))
+        .map(|word| word.into_owned());
+    let cmd = words.next().unwrap_or_default();
+    Ok((cmd, words.collect()))
+}
+
+/// DOCKER_CHECK_* variables of the hook
+pub fn env(event: &Event) -> Vec<(String, String)> {
+    PLACEHOLDERS
+        .iter()
+        .map(|placeholder| {
+            (
+                format!("{}{}", ENV_PREFIX, placeholder.to_uppercase()),
+                value(event, placeholder),
+            )
+        })
+        .collect()
+}
+
+/// Hook of a lifecycle event as resolved by the policy
+#[derive(Debug, Clone, PartialEq)]
+pub struct Hook {
+    pub template: String,
+    pub timeout: Duration,
+}
+
+struct Job {
+    template: String,
+    event: Event,
+    cmd: String,
+    args: Vec<String>,
+    env: Vec<(String, String)>,
+    input: Vec<u8>,
+    timeout: Duration,
+}
+
+fn run(job: Job, retry: &Retry, dead_letters: &DeadLettersConfig, logs: &HookLogs) {
+    let id = hook_logs::execution_id(&job.event, Utc::now());
+    let (id, log) = match logs.create(&id) {
+        Ok((id, path)) => (id, Some(path)),
+        Err(e) => {
+            warn!("hook id={} output won't be kept: {}", id, e);
+            (id, None)
+        }
+    };
+    let started = Instant::now();
+    let mut attempts = 0;
+    let result = retry.run(
+        || {
+            attempts += 1;
+            let attempt_started = Instant::now();
+            let result = run_command::run_command(&job.cmd, &job.args, &job.env, &job.input, job.timeout);
+            // every attempt is counted
+            match result {
+                Ok(_) => METRICS.hook_executed("0"),
+                Err(ref e) => METRICS.hook_executed(&e.status()),
+            }
+            if let Some(ref path) = log {
+                let elapsed = attempt_started.elapsed();
+                if let Err(e) = logs.append(path, attempts, &job.cmd, &job.args, &result, elapsed) {
+                    warn!("hook id={} {}", id, e);
+                }
+            }
+            result
+        },
+        HookError::is_retryable,
+    );
+    let log = log.map_or_else(|| "-".to_string(), |path| path.display().to_string());
+    match result {
+        Ok(_) => debug!(
+            "hook id={} event={} container={} cmd=\"{}\" status=0 attempts={} elapsed={:?} log={}",
+            id,
+            job.event.event.as_str(),
+            job.event.id,
+            job.cmd,
+            attempts,
+            started.elapsed(),
+            log
+        ),
+        Err(e) => {
+            warn!(
+                "hook id={} event={} container={} cmd=\"{}\" status={} attempts={} elapsed={:?} log={} error=\"{}\"",
+                id,
+                job.event.event.as_str(),
+                job.event.id,
+                job.cmd,
+                e.status(),
+                attempts,
+                started.elapsed(),
+                log,
+                e
+            );
+            let target = Target::hook(&Hook {
+                template: job.template,
+                timeout: job.timeout,
+            });
+            dead_letters::store(dead_letters, DeadLetter::new(target, &e.to_string(), vec![job.event]));
+        }
+    }
+}
+
+/// How the hooks are run, replaced on config reload
+struct Settings {
+    retry: Retry,
+    dead_letters: DeadLettersConfig,
+    logs: HookLogs,
+}
+
+impl Settings {
+    fn new(config: &HookRunnerConfig, dead_letters: &DeadLettersConfig) -> Self {
+        Self {
+            retry: Retry::new(config.retries, config.backoff, config.jitter),
+            dead_letters: dead_letters.clone(),
+            logs: HookLogs::new(config),
+        }
+    }
+}
+
+struct Queue {
+    jobs: VecDeque<Job>,
+    queue_size: usize,
+    max_concurrent: usize,
+    // running worker threads, the extra ones exit after their current hook when max_concurrent goes down
+    workers: usize,
+    settings: Arc<Settings>,
+    closed: bool,
+}
+
+struct Shared {
+    queue: Mutex<Queue>,
+    changed: Condvar,
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<Queue> {
+        // a panicking hook thread shouldn't stop the others
+        self.queue.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+fn work(shared: &Shared) {
+    loop {
+        let (job, settings) = {
+            let mut queue = shared.lock();
+            loop {
+                if queue.workers > queue.max_concurrent {
+                    queue.workers -= 1;
+                    // the hook it may have been woken up for goes to another worker
+                    shared.changed.notify_one();
+                    return;
+                }
+                if let Some(job) = queue.jobs.pop_front() {
+                    break (job, queue.settings.clone());
+                }
+                if queue.closed {
+                    queue.workers -= 1;
+                    return;
+                }
+                queue = shared.changed.wait(queue).unwrap_or_else(|e| e.into_inner());
+            }
+        };
+        run(job, &settings.retry, &settings.dead_letters, &settings.logs);
+    }
+}
+
+/// Runs at most `max_concurrent` hooks at the same time, so even long running hooks don't block the checker
+/// and a flapping container can't start an unbounded number of them.
+/// The worker threads exit after the queued hooks are done when the runner is shut down or dropped.
+/// A worker keeps its slot while it waits for a retry.
+pub struct Runner {
+    shared: Arc<Shared>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl Runner {
+    pub fn new(config: &HookRunnerConfig, dead_letters: &DeadLettersConfig) -> Self {
+        let shared = Arc::new(Shared {
+            queue: Mutex::new(Queue {
+                jobs: VecDeque::new(),
+                queue_size: config.queue_size,
+                max_concurrent: 0,
+                workers: 0,
+                settings: Arc::new(Settings::new(config, dead_letters)),
+                closed: false,
+            }),
+            changed: Condvar::new(),
+        });
+        let mut runner = Self {
+            shared,
+            workers: Vec::new(),
+        };
+        runner.reconfigure(config, dead_letters);
+        runner
+    }
+
+    /// Applies the limits of a new config. Queued and running hooks are kept,
+    /// workers are started or stopped to match `max_concurrent`.
+    pub fn reconfigure(&mut self, config: &HookRunnerConfig, dead_letters: &DeadLettersConfig) {
+        let missing = {
+            let mut queue = self.shared.lock();
+            queue.queue_size = config.queue_size;
+            queue.max_concurrent = config.max_concurrent;
+            queue.settings = Arc::new(Settings::new(config, dead_letters));
+            let missing = config.max_concurrent.saturating_sub(queue.workers);
+            queue.workers += missing;
+            missing
+        };
+        // idle workers above the limit exit
+        self.shared.changed.notify_all();
+        self.workers.retain(|worker| !worker.is_finished());
+        for _ in 0..missing {
+            let shared = self.shared.clone();
+            self.workers.push(thread::spawn(move || work(&shared)));
+        }
+    }
+
+    fn close(&self) {
+        self.shared.lock().closed = true;
+        self.shared.changed.notify_all();
+    }
+
+    /// Waits until the queued hooks are done
+    pub fn shutdown(mut self) {
+        self.close();
+        for worker in self.workers.drain(..) {
+            if worker.join().is_err() {
+                error!("Hook worker has panicked");
+            }
+        }
+    }
+
+    /// Queues the hook, returns false if it was dropped
+    pub fn spawn(&self, hook: &Hook, event: &Event) -> bool {
+        let (cmd, args) = match command(&hook.template, event) {
+            Ok(command) => command,
+            Err(e) => {
+                METRICS.hook_executed("error");
+                warn!("Cannot execute hook: {}", e);
+                return false;
+            }
+        };
+        let job = Job {
+            template: hook.template.clone(),
+            event: event.clone(),
+            cmd,
+            args,
+            env: env(event),
+            input: serde_json::to_vec(event).unwrap_or_default(),
+            timeout: hook.timeout,
+        };
+        let mut queue = self.shared.lock();
+        if queue.closed {
+            METRICS.hook_dropped();
+            error!("Hook workers have stopped, dropping \"{}\"", job.cmd);
+            return false;
+        }
+        if queue.jobs.len() >= queue.queue_size {
+            METRICS.hook_dropped();
+            warn!(
+                "Hook queue is full ({} waiting), dropping \"{}\" of the {} event of container {}",
+                queue.queue_size,
+                job.cmd,
+                event.event.as_str(),
+                event.id
+            );
+            return false;
+        }
+        queue.jobs.push_back(job);
+        self.shared.changed.notify_one();
+        true
+    }
+}
+
+impl Drop for Runner {
+    // workers finish the queued hooks and exit, `shutdown` waits for that
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use notifiers::tests::event;
+    use std::{env, fs, process};
+
+    #[test]
+    fn container_id_is_appended() {
+        let (cmd, args) = command("example/notify-slack.sh", &event()).unwrap();
+        assert_eq!(cmd, "example/notify-slack.sh");
+        assert_eq!(args, vec!["0123abcd"]);
+    }
+
+    #[test]
+    fn placeholders_are_replaced() {
+        let template = "/usr/local/bin/notify --container={name} 'event {event} on {host}' {restarts}";
+        let (cmd, args) = command(template, &event()).unwrap();
+        assert_eq!(cmd, "/usr/local/bin/notify");
+        assert_eq!(args, vec!["--container=db", "event hard_failure on node-1", "3"]);
+    }
+
+    #[test]
+    fn invalid_templates() {
+        assert!(parse("").unwrap_err().contains("empty"));
+        assert!(parse("notify.sh {container}")
+            .unwrap_err()
+            .contains("unknown placeholder {container}"));
+        assert!(parse("notify.sh 'unclosed").unwrap_err().contains("cannot be parsed"));
+    }
+
+    #[test]
+    fn env_test() {
+        let env = env(&event());
+        assert!(env.contains(&("DOCKER_CHECK_ID".to_string(), "0123abcd".to_string())));
+        assert!(env.contains(&("DOCKER_CHECK_EVENT".to_string(), "hard_failure".to_string())));
+        assert!(env.contains(&("DOCKER_CHECK_IMAGE".to_string(), "postgres:11".to_string())));
+        assert_eq!(env.len(), PLACEHOLDERS.len());
+    }
+
+    #[test]
+    fn full_queue_drops_hooks() {
+        // no workers, so nothing leaves the queue
+        let runner = Runner::new(
+            &HookRunnerConfig {
+                max_concurrent: 0,
+                queue_size: 1,
+                ..Default::default()
+            },
+            &DeadLettersConfig::default(),
+        );
+        let hook = Hook {
+            template: "tests/run_command.sh".to_string(),
+            timeout: Duration::from_secs(1),
+        };
+        assert!(runner.spawn(&hook, &event()));
+        assert!(!runner.spawn(&hook, &event()));
+    }
+
+    #[test]
+    fn reconfigure_keeps_queued_hooks() {
+        let config = |queue_size| HookRunnerConfig {
+            max_concurrent: 0,
+            queue_size,
+            ..Default::default()
+        };
+        let mut runner = Runner::new(&config(1), &DeadLettersConfig::default());
+        let hook = Hook {
+            template: "tests/run_command.sh".to_string(),
+            timeout: Duration::from_secs(1),
+        };
+        assert!(runner.spawn(&hook, &event()));
+        runner.reconfigure(&config(2), &DeadLettersConfig::default());
+        assert!(runner.spawn(&hook, &event()));
+        assert!(!runner.spawn(&hook, &event()));
+        assert_eq!(runner.shared.lock().jobs.len(), 2);
+    }
+
+    #[test]
+    fn failed_hook_is_retried_logged_and_stored() {
+        let path = env::temp_dir().join(format!("docker-check-hooks-{}.jsonl", process::id()));
+        let _ = fs::remove_file(&path);
+        let dead_letters = DeadLettersConfig {
+            path: Some(path.to_str().unwrap().to_string()),
+        };
+        let mut failing = event();
+        failing.id = "some-id-error".to_string();
+        let job = Job {
+            template: "tests/run_command.sh".to_string(),
+            event: failing.clone(),
+            cmd: "tests/run_command.sh".to_string(),
+            args: vec![failing.id.clone()],
+            env: Vec::new(),
+            input: Vec::new(),
+            timeout: Duration::from_secs(10),
+        };
+        let log_dir = env::temp_dir().join(format!("docker-check-hook-output-{}", process::id()));
+        let _ = fs::remove_dir_all(&log_dir);
+        let logs = HookLogs::new(&HookRunnerConfig {
+            log_dir: log_dir.to_str().unwrap().to_string(),
+            ..Default::default()
+        });
+        run(job, &Retry::new(1, Duration::from_millis(1), 0.0), &dead_letters, &logs);
+
+        let content = fs::read_to_string(&path).unwrap();
+        let letter: DeadLetter = serde_json::from_str(content.trim()).unwrap();
+        assert_eq!(
+            letter.target,
+            Target::Hook {
+                template: "tests/run_command.sh".to_string(),
+                timeout: Some("10s".to_string()),
+            }
+        );
+        assert_eq!(letter.error, "exited with code 1");
+        assert_eq!(letter.events[0].id, "some-id-error");
+        fs::remove_file(&path).unwrap();
+
+        // both attempts are in the output of the execution
+        let outputs = fs::read_dir(&log_dir).unwrap().collect::<Vec<_>>();
+        assert_eq!(outputs.len(), 1);
+        let output_path = outputs[0].as_ref().unwrap().path();
+        assert!(output_path.to_str().unwrap().ends_with("-db-hard_failure.log"));
+        let output = fs::read_to_string(&output_path).unwrap();
+        assert_eq!(output.matches("Finished with error").count(), 2);
+        assert!(output.contains("# attempt 2 at "));
+        fs::remove_dir_all(&log_dir).unwrap();
+    }
+}
@@ -1,18 +1,39 @@
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
-use std::thread;
 use std::time::Duration;
+mod aws;
+mod dead_letters;
 mod docker_checker;
+mod endpoint;
+mod hook_logs;
+mod hooks;
 mod label_filters;
+mod metrics;
+mod notifiers;
+extern crate clap;
 extern crate config as configuration;
 extern crate ctrlc;
 
+extern crate hmac;
+extern crate lettre;
+extern crate lettre_email;
+extern crate libc;
+extern crate native_tls;
 extern crate os_pipe;
+extern crate rand;
 extern crate regex;
+extern crate reqwest;
 extern crate serde;
+extern crate shell_words;
+#[macro_use]
+extern crate serde_json;
+extern crate sha2;
+extern crate signal_hook;
+extern crate tiny_http;
 
 #[macro_use]
 extern crate human_panic;
+extern crate humantime;
 
 #[macro_use]
 extern crate serde_derive;
@@ -26,17 +47,37 @@ extern crate chrono;
 extern crate fern;
 
 extern crate dockworker;
+mod cli;
 pub mod config;
+mod policy;
+mod reload;
+mod retry;
 mod run_command;
+mod status;
+mod validate;
 
 use config::LoggingConfig;
 use std::process;
 use std::str::FromStr;
 
+/// Reads the config and applies overrides from the command line
+fn load_settings(filename: &str) -> Result<config::Config, String> {
+    let mut config = config::get_settings(filename)?;
+    if let Some(ref level) = ARGS.log_level {
+        config.logging.checker = level.clone();
+    }
+    if ARGS.dry_run {
+        config.containers.dry_run = true;
+    }
+    Ok(config)
+}
+
 lazy_static! {
-    static ref SETTINGS: config::Config = {
-        match config::get_settings("settings") {
-            Ok(config) => config,
+    static ref ARGS: cli::Args = cli::parse();
+    // config at the start, the checker keeps the current one after reloads
+    static ref SETTINGS: Arc<config::Config> = {
+        match load_settings(&ARGS.config) {
+            Ok(config) => Arc::new(config),
             Err(e) => {
                 println!("[ERROR]: Cannot read config. Error: {}", e);
                 process::exit(0x0100);
@@ -67,101 +108,201 @@ pub fn setup_logger(config: &LoggingConfig) -> Result<(), fern::InitError> {
     Ok(())
 }
 
-use docker_checker::{ContainerStats, DockerChecker};
+use docker_checker::{ContainerStats, DockerChecker, Failure, Health};
 use dockworker::container::{Container, HealthState};
+use metrics::{COMPOSE_SERVICE_LABEL, METRICS};
+use notifiers::{Event, EventKind};
+use policy::{Policy, PolicyRef};
+use reload::Reloader;
 
-fn check_docker_containers(finished: Arc<AtomicBool>) -> Result<(), String> {
-    let mut dc = DockerChecker::new(&SETTINGS.docker.connect_uri, finished, &*SETTINGS)?;
-    dc.watch_for(Duration::from_secs(2), |this: &DockerChecker, container: &Container| {
-        let info;
-        let client = &this.client;
-        let stats = &mut this.stats.borrow_mut();
-        let config = &this.config;
-        match client.container_info(container) {
-            Ok(x) => {
-                info = x;
-            }
-            Err(e) => {
-                error!(
-                    "Error getting info for container (could be possible that container was removed): {}. Skipping..",
-                    e
-                );
-                return;
-            }
-        };
-        let container_state = match info.State.Health {
-            Some(health_state) => health_state.Status,
-            None => {
-                warn!("Container {} doesn't have a healthcheck, skipping..", &info.Name);
-                return;
-            }
-        };
-        let container_stats = stats.entry(info.Id.clone()).or_insert(ContainerStats::default());
-        if container_state == HealthState::Healthy {
-            debug!("Container {} is okay: {:?}", &info.Name, container_stats);
-            container_stats.count += 1;
-        } else if container_state == HealthState::Unhealthy {
+fn check_container(this: &DockerChecker, container: &Container, policy_ref: PolicyRef) -> Result<(), String> {
+    let info;
+    let client = &this.client;
+    let stats = &mut this.stats.borrow_mut();
+    let policy = Policy::resolve(&this.config, policy_ref, &container.Id, container.Labels.as_ref());
+    match client.container_info(container) {
+        Ok(x) => {
+            info = x;
+        }
+        Err(e) => {
+            METRICS.docker_api_error();
+            return Err(format!(
+                "Error getting info for container {} (could be possible that container was removed): {}",
+                container.Id, e
+            ));
+        }
+    };
+    let (container_state, health_log) = match info.State.Health {
+        Some(health_state) => {
+            let health_log = health_state
+                .Log
+                .last()
+                .map(|log| log.Output.clone())
+                .unwrap_or_default();
+            (health_state.Status, health_log)
+        }
+        None => {
+            warn!("Container {} doesn't have a healthcheck, skipping..", &info.Name);
+            return Ok(());
+        }
+    };
+    let container_stats = stats.entry(info.Id.clone()).or_insert(ContainerStats::default());
+    let was_unhealthy = container_stats.health == Some(Health::Unhealthy);
+    container_stats.health = Some(Health::from(&container_state));
+    container_stats.critical = policy.critical;
+    container_stats.hard_failures = policy.hard_failures;
+    container_stats.on_disappeared = policy.hook(EventKind::Disappeared);
+    container_stats.name = info.Name.clone();
+    container_stats.image = container.Image.clone();
+    container_stats.service = container
+        .Labels
+        .as_ref()
+        .and_then(|labels| labels.get(COMPOSE_SERVICE_LABEL))
+        .cloned()
+        .unwrap_or_default();
+    let event = |kind: EventKind, restarts: u32| Event::new(kind, container, &info.Name, &health_log, restarts);
+    if container_state == HealthState::Healthy {
+        debug!("Container {} is okay: {:?}", &info.Name, container_stats);
+        container_stats.count += 1;
+        if container_stats.recovering {
+            container_stats.recovering = false;
+            container_stats.hard_failed = false;
+            let recovered = event(EventKind::Recovered, container_stats.restarts);
+            this.emit(recovered, policy.hook(EventKind::Recovered), container_stats);
+        }
+    } else if container_state == HealthState::Unhealthy {
+        if !container_stats.failure_due(this.config.containers.poll_interval) {
             debug!(
-                "Container {} is not okay, restarting; After {} failures it will be restarted! Current count: {}",
-                &info.Name, config.containers.consecutive_failures, container_stats.consecutive_failures
+                "Container {} is still unhealthy, the failure of this interval is counted already",
+                &info.Name
             );
-            container_stats.consecutive_failures += 1;
+            return Ok(());
+        }
+        debug!(
+            "Container {} is not okay, restarting; After {} failures it will be restarted! Current count: {}",
+            &info.Name, policy.consecutive_failures, container_stats.consecutive_failures
+        );
+        let dry_run = this.config.containers.dry_run;
+        // failures after a restart are covered by the restart and hard_failure events
+        let first_failure = !was_unhealthy && !container_stats.recovering;
+        let failure = container_stats.register_failure(&policy, dry_run);
+        if first_failure {
+            let unhealthy = event(EventKind::Unhealthy, container_stats.restarts);
+            this.emit(unhealthy, policy.hook(EventKind::Unhealthy), container_stats);
+        }
 
-            if container_stats.consecutive_failures - 1 == config.containers.consecutive_failures {
+        if failure != Failure::Counted {
+            // the would-be restarts of a dry run never show up as real ones
+            let restarts = if dry_run {
+                container_stats.dry_run_restarts
+            } else {
+                container_stats.restarts
+            };
+            warn!(
+                "Container {} scored {} consecutive_failures and going to be restarted",
+                &info.Name, policy.consecutive_failures
+            );
+            let failed_container = container.Id.clone();
+            let stop_timeout = policy.stop_timeout;
+            let docker_config = this.config.docker.clone();
+            if dry_run {
                 warn!(
-                    "Container {} scored {} consecutive_failures and going to be restarted",
-                    &info.Name, config.containers.consecutive_failures
+                    "dry_run action=would_restart container={} name={} consecutive_failures={} stop_timeout={:?} total={}",
+                    &failed_container,
+                    &info.Name,
+                    policy.consecutive_failures,
+                    stop_timeout,
+                    restarts
                 );
-                let failed_container = container.Id.clone();
-                thread::spawn(move || {
+            } else {
+                this.spawn_task(move || {
                     // Won't block the main thread anymore, rarely can fail.
-                    let client_for_restart = DockerChecker::get_new_client(&SETTINGS.docker.connect_uri);
+                    let client_for_restart = DockerChecker::get_new_client(&docker_config);
                     match client_for_restart {
                         Ok(client) => {
-                            client
-                                .restart_container(&failed_container, Duration::from_secs(5))
-                                .unwrap();
+                            client.restart_container(&failed_container, stop_timeout).unwrap();
                             warn!("Container {} restarted successfully!", &failed_container);
                         }
                         Err(e) => error!(
                             "Cannot get the docker client. URI: {}, error: {}",
-                            &SETTINGS.docker.connect_uri, e
+                            &docker_config.endpoint, e
                         ),
                     }
                 });
+            }
 
-                container_stats.restarts += 1;
-                container_stats.consecutive_failures = 0;
-
-                if container_stats.restarts >= config.containers.hard_failures as u32 {
-                    let mut args = Vec::new();
-                    args.push(container.Id.clone());
-                    let cmd = config.containers.run_on_failure.clone();
-                    thread::spawn(move || {
-                        let result = run_command::run_command(&cmd, &args);
-                        match result {
-                            Ok(output) => {
-                                if !output.status.success() {
-                                    warn!(
-                                        "Executed script \"{}\".Got non-zero({}) exit status.\nOutput: {}",
-                                        cmd, output.status, output.output
-                                    );
-                                } else {
-                                    debug!("Executed script \"{}\" successfully.\nOutput: {}", cmd, output.output);
+            let restart = event(EventKind::Restart, restarts);
+            this.emit(restart, policy.hook(EventKind::Restart), container_stats);
+
+            if failure == Failure::HardFailure {
+                let aws_config = &this.config.aws;
+                if aws_config.enabled && aws_config.asg.healthcheck {
+                    if dry_run {
+                        warn!(
+                            "dry_run action=would_set_instance_health container={} name={} restarts={} health=Unhealthy",
+                            &container.Id, &info.Name, restarts
+                        );
+                    } else {
+                        let aws_config = aws_config.clone();
+                        this.spawn_task(move || {
+                            match aws::Aws::new(&aws_config).and_then(|aws| aws.set_instance_unhealthy()) {
+                                Ok(instance_id) => {
+                                    warn!("Instance {} is marked Unhealthy in its Auto Scaling group", instance_id)
                                 }
+                                Err(e) => error!("Cannot set instance health in the Auto Scaling group: {}", e),
                             }
-                            Err(e) => {
-                                warn!("Cannot execute command \"{}\". Error: {:?}", cmd, e);
-                            }
-                        }
-                    });
+                        });
+                    }
                 }
+                let hard_failure = event(EventKind::HardFailure, restarts);
+                this.emit(hard_failure, policy.hook(EventKind::HardFailure), container_stats);
             }
-        } else {
-            debug!("Container {} is in state: {}", &info.Name, container_state);
         }
-    })
-    .map_err(|e| {
+    } else {
+        debug!("Container {} is in state: {}", &info.Name, container_state);
+    }
+    Ok(())
+}
+
+/// Single check cycle for `--once`, returns the number of unhealthy containers and the ones that couldn't be checked
+fn check_docker_containers_once() -> Result<usize, String> {
+    let dc = DockerChecker::new(Arc::new(AtomicBool::new(false)), SETTINGS.clone())?;
+    let tick = dc.check_all(check_container);
+    let unhealthy = dc
+        .stats
+        .borrow()
+        .values()
+        .filter(|container_stats| container_stats.health == Some(Health::Unhealthy))
+        .count();
+    // restarts, hooks and notifications of the cycle are done before the process exits
+    dc.shutdown();
+    Ok(unhealthy + tick?.errors)
+}
+
+fn check_docker_containers(finished: Arc<AtomicBool>) -> Result<(), String> {
+    if SETTINGS.metrics.enabled {
+        metrics::serve(&SETTINGS.metrics.listen)?;
+    }
+    if SETTINGS.aws.enabled && SETTINGS.aws.cloudwatch.enabled {
+        aws::cloudwatch::spawn(SETTINGS.aws.clone())?;
+    }
+    if SETTINGS.status.enabled {
+        // a few missed loops are tolerated before the checker is reported as stuck
+        let tick = if SETTINGS.docker.use_events {
+            SETTINGS.docker.reconcile_interval
+        } else {
+            SETTINGS.containers.poll_interval
+        };
+        status::serve(&SETTINGS.status.listen, tick * 3 + Duration::from_secs(30))?;
+    }
+    let reloader = Reloader::new(&ARGS.config, SETTINGS.reload.watch_file, load_settings)?;
+    let mut dc = DockerChecker::new(finished, SETTINGS.clone())?.with_reloader(reloader);
+    let result = if SETTINGS.docker.use_events {
+        dc.watch_events(check_container)
+    } else {
+        dc.watch_for(check_container)
+    };
+    result.map_err(|e| {
         error!("Error getting info: {}", e);
         e.to_string()
     })?;
@@ -169,14 +310,60 @@ fn check_docker_containers(finished: Arc<AtomicBool>) -> Result<(), String> {
     Ok(())
 }
 
+/// `docker-check validate`: prints every problem of the config, returns the exit code
+fn validate_config(filename: &str) -> i32 {
+    let problems = validate::validate(filename);
+    if problems.is_empty() {
+        println!("{}: OK", filename);
+        return 0;
+    }
+    for problem in problems.iter() {
+        println!("{}: {}", filename, problem);
+    }
+    println!("{} problem(s) found", problems.len());
+    1
+}
+
+/// `docker-check replay-dead-letters`: returns the exit code, non-zero if any dead letter is left
+fn replay_dead_letters() -> i32 {
+    setup_logger(&SETTINGS.logging).expect("Cannot setup logger. Shouldn't be possible in most cases");
+    match dead_letters::replay(&SETTINGS) {
+        Ok(replayed) => {
+            print!("{} dead letter(s) delivered", replayed.delivered);
+            if replayed.superseded > 0 {
+                print!(", {} superseded event(s) dropped", replayed.superseded);
+            }
+            if replayed.failed > 0 {
+                println!(", {} failed and were kept", replayed.failed);
+                return 1;
+            }
+            println!();
+            0
+        }
+        Err(e) => {
+            println!("[ERROR]: Cannot replay dead letters: {}", e);
+            1
+        }
+    }
+}
+
 fn main() {
     // At least that will allow some reports (hope it'll never fire though)
     // But there are a bit of unwraps scattered over the place
     setup_panic!();
+    if ARGS.command == cli::Command::Validate {
+        process::exit(validate_config(&ARGS.config));
+    }
+    if ARGS.command == cli::Command::ReplayDeadLetters {
+        process::exit(replay_dead_letters());
+    }
     let settings = &SETTINGS;
 
     setup_logger(&settings.logging).expect("Cannot setup logger. Shouldn't be possible in most cases");
-    debug!("Got settings: {:?}", **settings);
+    debug!("Got settings: {:?}", ***settings);
+    if settings.containers.dry_run {
+        warn!("Running in dry-run mode: containers won't be restarted and hooks won't be executed");
+    }
 
     // no networking for now
     let finished = Arc::new(AtomicBool::new(false));
@@ -192,6 +379,21 @@ fn main() {
             ! Add rusoto and send-healthcheck-request
     */
 
+    if ARGS.once {
+        match check_docker_containers_once() {
+            Ok(0) => info!("All containers are healthy"),
+            Ok(unhealthy) => {
+                error!("{} container(s) are unhealthy or couldn't be checked", unhealthy);
+                process::exit(1);
+            }
+            Err(e) => {
+                error!("Fatal: {}", e);
+                process::exit(2);
+            }
+        }
+        return;
+    }
+
     check_docker_containers(finished.clone())
         .map_err(|e| error!("Fatal: {}", e))
         .unwrap_or(());
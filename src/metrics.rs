@@ -0,0 +1,322 @@
+/* Metrics of the checker in the Prometheus text format.
+    The checker publishes a snapshot of its stats every tick and after every handled docker event,
+    hooks and docker calls bump the counters.
+    Served over HTTP on `[metrics] listen` when enabled and/or written to `[metrics] textfile_dir`
+    for the node_exporter textfile collector.
+*/
+use chrono::{DateTime, Utc};
+use docker_checker::Health;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::process;
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+use tiny_http::{Header, Response, Server};
+
+pub const COMPOSE_SERVICE_LABEL: &str = "com.docker.compose.service";
+pub const TEXTFILE_NAME: &str = "docker_check.prom";
+
+lazy_static! {
+    pub static ref METRICS: Metrics = Metrics::default();
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ContainerSnapshot {
+    pub id: String,
+    pub name: String,
+    pub image: String,
+    pub service: String,
+    pub health: Option<Health>,
+    pub count: u32,
+    pub restarts: u32,
+    pub consecutive_failures: u16,
+    pub hard_failures: u16,
+    pub hard_failed: bool,
+    pub critical: bool,
+    pub last_restart: Option<DateTime<Utc>>,
+    pub dry_run_restarts: u32,
+    pub dry_run_hooks: u32,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    containers: Vec<ContainerSnapshot>,
+    loops: u64,
+    loop_duration: Duration,
+    last_loop: Option<Instant>,
+    docker_api_errors: u64,
+    // by exit status: exit code, "signal", "timeout" or "error" when hook couldn't be started
+    hook_executions: BTreeMap<String, u64>,
+    // didn't fit into the queue of the hook runner
+    hooks_dropped: u64,
+}
+
+#[derive(Debug, Default)]
+pub struct Metrics {
+    state: Mutex<State>,
+}
+
+impl Metrics {
+    fn with_state<T, F: FnOnce(&mut State) -> T>(&self, f: F) -> T {
+        // metrics are not worth crashing for if some thread panicked while holding the lock
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        f(&mut state)
+    }
+
+    pub fn set_containers(&self, containers: Vec<ContainerSnapshot>) {
+        self.with_state(|state| state.containers = containers)
+    }
+
+    pub fn observe_loop(&self, duration: Duration) {
+        self.with_state(|state| {
+            state.loops += 1;
+            state.loop_duration = duration;
+            state.last_loop = Some(Instant::now());
+        })
+    }
+
+    pub fn docker_api_error(&self) {
+        self.with_state(|state| state.docker_api_errors += 1)
+    }
+
+    pub fn hook_executed(&self, status: &str) {
+        self.with_state(|state| *state.hook_executions.entry(status.to_string()).or_insert(0) += 1)
+    }
+
+    pub fn hook_dropped(&self) {
+        self.with_state(|state| state.hooks_dropped += 1)
+    }
+
+    /// When the last check loop has finished
+    pub fn last_loop(&self) -> Option<Instant> {
+        self.with_state(|state| state.last_loop)
+    }
+
+    pub fn containers(&self) -> Vec<ContainerSnapshot> {
+        self.with_state(|state| state.containers.clone())
+    }
+
+    /// Prometheus text exposition format
+    pub fn render(&self) -> String {
+        self.with_state(|state| {
+            let mut out = String::new();
+            render_containers(&mut out, &state.containers);
+            metric_header(&mut out, "docker_check_loops_total", "counter", "Number of check loops");
+            let _ = writeln!(out, "docker_check_loops_total {}", state.loops);
+            metric_header(
+                &mut out,
+                "docker_check_loop_duration_seconds",
+                "gauge",
+                "Duration of the last check loop",
+            );
+            let duration = state.loop_duration.as_secs() as f64 + f64::from(state.loop_duration.subsec_nanos()) / 1e9;
+            let _ = writeln!(out, "docker_check_loop_duration_seconds {}", duration);
+            metric_header(
+                &mut out,
+                "docker_check_docker_api_errors_total",
+                "counter",
+                "Failed docker API calls",
+            );
+            let _ = writeln!(out, "docker_check_docker_api_errors_total {}", state.docker_api_errors);
+            metric_header(
+                &mut out,
+                "docker_check_hook_executions_total",
+                "counter",
+                "Hook executions by exit status",
+            );
+            for (status, count) in state.hook_executions.iter() {
+                let _ = writeln!(
+                    out,
+                    "docker_check_hook_executions_total{{status=\"{}\"}} {}",
+                    escape(status),
+                    count
+                );
+            }
+            metric_header(
+                &mut out,
+                "docker_check_hooks_dropped_total",
+                "counter",
+                "Hook executions dropped because the queue was full",
+            );
+            let _ = writeln!(out, "docker_check_hooks_dropped_total {}", state.hooks_dropped);
+            out
+        })
+    }
+}
+
+fn metric_header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+fn render_containers(out: &mut String, containers: &[ContainerSnapshot]) {
+    let metrics: [(&str, &str, &str, fn(&ContainerSnapshot) -> String); 6] = [
+        (
+            "docker_check_container_healthy",
+            "gauge",
+            "1 if the container is healthy, 0 if unhealthy, -1 if its health is unknown yet",
+            |c| {
+                match c.health {
+                    Some(Health::Healthy) => "1",
+                    Some(Health::Unhealthy) => "0",
+                    _ => "-1",
+                }
+                .to_string()
+            },
+        ),
+        (
+            "docker_check_container_healthy_checks_total",
+            "counter",
+            "Number of checks the container was healthy",
+            |c| c.count.to_string(),
+        ),
+        (
+            "docker_check_container_restarts_total",
+            "counter",
+            "Number of restarts made by the checker",
+            |c| c.restarts.to_string(),
+        ),
+        (
+            "docker_check_container_consecutive_failures",
+            "gauge",
+            "Current number of consecutive failed checks",
+            |c| c.consecutive_failures.to_string(),
+        ),
+        (
+            "docker_check_container_dry_run_restarts_total",
+            "counter",
+            "Number of restarts skipped because of dry_run",
+            |c| c.dry_run_restarts.to_string(),
+        ),
+        (
+            "docker_check_container_dry_run_hooks_total",
+            "counter",
+            "Number of hook executions skipped because of dry_run",
+            |c| c.dry_run_hooks.to_string(),
+        ),
+    ];
+    for (name, kind, help, value) in metrics.iter() {
+        metric_header(out, name, kind, help);
+        // no container id: it changes on every recreation, so every deploy would start new series
+        for c in containers.iter() {
+            let _ = writeln!(
+                out,
+                "{}{{name=\"{}\",image=\"{}\",service=\"{}\"}} {}",
+                name,
+                escape(&c.name),
+                escape(&c.image),
+                escape(&c.service),
+                value(c)
+            );
+        }
+    }
+}
+
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+/// Writes metrics to `dir/docker_check.prom`. The file is replaced atomically,
+/// so the collector never reads a half-written one.
+pub fn write_textfile(metrics: &Metrics, dir: &str) -> io::Result<()> {
+    let dir = Path::new(dir);
+    // should be in the same directory (and filesystem) for rename to be atomic.
+    // Name doesn't end with .prom, so the collector ignores it
+    let tmp = dir.join(format!(".{}.{}.tmp", TEXTFILE_NAME, process::id()));
+    fs::write(&tmp, metrics.render())?;
+    fs::rename(&tmp, dir.join(TEXTFILE_NAME)).map_err(|e| {
+        let _ = fs::remove_file(&tmp);
+        e
+    })
+}
+
+/// Serves `/metrics` in a separate thread
+pub fn serve(listen: &str) -> Result<(), String> {
+    let server = Server::http(listen).map_err(|e| format!("Cannot listen on {}: {}", listen, e))?;
+    info!("Serving metrics on http://{}/metrics", listen);
+    thread::spawn(move || {
+        for request in server.incoming_requests() {
+            let response = if request.url() == "/metrics" {
+                let content_type = Header::from_bytes(&b"Content-Type"[..], &b"text/plain; version=0.0.4"[..])
+                    .expect("static header is valid");
+                Response::from_string(METRICS.render()).with_header(content_type)
+            } else {
+                Response::from_string("Not found").with_status_code(404)
+            };
+            if let Err(e) = request.respond(response) {
+                debug!("Cannot send metrics response: {}", e);
+            }
+        }
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_test() {
+        let metrics = Metrics::default();
+        metrics.set_containers(vec![ContainerSnapshot {
+            id: "dfdb8ee577c1".to_string(),
+            name: "/web_1".to_string(),
+            image: "nginx:latest".to_string(),
+            service: "web".to_string(),
+            health: Some(Health::Unhealthy),
+            count: 42,
+            restarts: 2,
+            consecutive_failures: 3,
+            dry_run_restarts: 1,
+            dry_run_hooks: 2,
+            ..Default::default()
+        }]);
+        metrics.observe_loop(Duration::from_millis(1500));
+        metrics.docker_api_error();
+        metrics.hook_executed("0");
+        metrics.hook_executed("0");
+        metrics.hook_executed("1");
+        metrics.hook_executed("timeout");
+        metrics.hook_dropped();
+
+        let out = metrics.render();
+        let labels = "{name=\"/web_1\",image=\"nginx:latest\",service=\"web\"}";
+        assert!(out.contains(&format!("docker_check_container_healthy{} 0\n", labels)));
+        assert!(out.contains(&format!("docker_check_container_healthy_checks_total{} 42\n", labels)));
+        assert!(out.contains(&format!("docker_check_container_restarts_total{} 2\n", labels)));
+        assert!(out.contains(&format!("docker_check_container_consecutive_failures{} 3\n", labels)));
+        assert!(out.contains(&format!("docker_check_container_dry_run_restarts_total{} 1\n", labels)));
+        assert!(out.contains(&format!("docker_check_container_dry_run_hooks_total{} 2\n", labels)));
+        assert!(out.contains("docker_check_loops_total 1\n"));
+        assert!(out.contains("docker_check_loop_duration_seconds 1.5\n"));
+        assert!(out.contains("docker_check_docker_api_errors_total 1\n"));
+        assert!(out.contains("docker_check_hook_executions_total{status=\"0\"} 2\n"));
+        assert!(out.contains("docker_check_hook_executions_total{status=\"1\"} 1\n"));
+        assert!(out.contains("docker_check_hook_executions_total{status=\"timeout\"} 1\n"));
+        assert!(out.contains("docker_check_hooks_dropped_total 1\n"));
+        assert!(out.contains("# TYPE docker_check_container_restarts_total counter\n"));
+    }
+
+    #[test]
+    fn write_textfile_test() {
+        let dir = ::std::env::temp_dir().join(format!("docker-check-textfile-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let metrics = Metrics::default();
+        metrics.docker_api_error();
+        write_textfile(&metrics, dir.to_str().unwrap()).unwrap();
+        let content = fs::read_to_string(dir.join(TEXTFILE_NAME)).unwrap();
+        assert!(content.contains("# TYPE docker_check_docker_api_errors_total counter"));
+        // only the final file is left behind
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn escape_test() {
+        assert_eq!(escape("a\"b\\c\nd"), "a\\\"b\\\\c\\nd");
+    }
+}
@@ -0,0 +1,125 @@
+/* Alertmanager API v2: an alert fires when a container reaches hard failures and is resolved
+    (endsAt set to now) when it's healthy again or gone. Alerts are matched by their labels: alertname, host and container.
+*/
+use super::webhook::JsonEndpoint;
+use super::{Event, EventKind, Notifier};
+use chrono::{DateTime, Duration, Utc};
+use config::NotifierConfig;
+use serde_json::Value;
+
+const ALERT_NAME: &str = "DockerCheckContainerFailed";
+// the alert is sent only once, so it has to outlive the default resolve_timeout of Alertmanager.
+// Alerts that are never resolved, e.g. because the checker is gone, expire after this.
+const ALERT_TTL_HOURS: i64 = 24;
+
+pub struct Alertmanager {
+    endpoint: JsonEndpoint,
+}
+
+impl Alertmanager {
+    pub fn new(config: &NotifierConfig) -> Result<Self, String> {
+        let url = config
+            .url
+            .as_ref()
+            .ok_or_else(|| "`url` of Alertmanager is required for the alertmanager notifier".to_string())?;
+        let url = format!("{}/api/v2/alerts", url.trim_end_matches('/'));
+        Ok(Self {
+            endpoint: JsonEndpoint::new(url, config)?,
+        })
+    }
+}
+
+/// Alerts document for the event, None for the events that don't change the alert
+pub(crate) fn document(event: &Event) -> Option<Value> {
+    let time = DateTime::parse_from_rfc3339(&event.time)
+        .map(|time| time.with_timezone(&Utc))
+        .unwrap_or_else(|_| Utc::now());
+    let ends_at = match event.event {
+        EventKind::HardFailure => time + Duration::hours(ALERT_TTL_HOURS),
+        // a removed container never recovers
+        EventKind::Recovered | EventKind::Disappeared => time,
+        _ => return None,
+    };
+    let name = event.name.trim_start_matches('/');
+    Some(json!([{
+        "labels": {
+            "alertname": ALERT_NAME,
+            "severity": "critical",
+            "host": event.host,
+            "container": name,
+            "image": event.image,
+        },
+        "annotations": {
+            "summary": format!("Container {} reached hard failures on {}", name, event.host),
+            "health_log": event.health_log,
+            "restarts": event.restarts.to_string(),
+        },
+        "endsAt": ends_at.to_rfc3339(),
+    }]))
+}
+
+impl Notifier for Alertmanager {
+    fn notify(&self, events: &[Event]) -> Result<(), String> {
+        for document in events.iter().filter_map(document) {
+            self.endpoint.post(&document)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use config::NotifierKind;
+    use notifiers::tests::event;
+    use serde_json;
+    use std::io::Read;
+    use std::sync::mpsc;
+    use std::thread;
+    use tiny_http::{Response, Server};
+
+    #[test]
+    fn document_test() {
+        let mut event = event();
+        let firing = document(&event).unwrap();
+        assert_eq!(firing[0]["labels"]["alertname"], ALERT_NAME);
+        assert_eq!(firing[0]["labels"]["container"], "db");
+        assert_eq!(firing[0]["endsAt"], "2019-01-02T00:00:00+00:00");
+
+        event.event = EventKind::Recovered;
+        let resolved = document(&event).unwrap();
+        assert_eq!(resolved[0]["labels"], firing[0]["labels"]);
+        assert_eq!(resolved[0]["endsAt"], "2019-01-01T00:00:00+00:00");
+        event.event = EventKind::Disappeared;
+        assert_eq!(document(&event), Some(resolved));
+
+        event.event = EventKind::Restart;
+        assert_eq!(document(&event), None);
+    }
+
+    #[test]
+    fn posts_to_alerts_api() {
+        let server = Server::http("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/", server.server_addr());
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            let mut request = server.recv().unwrap();
+            let mut body = String::new();
+            request.as_reader().read_to_string(&mut body).unwrap();
+            sender.send((request.url().to_string(), body)).unwrap();
+            request.respond(Response::from_string("")).unwrap();
+        });
+
+        let config = NotifierConfig {
+            kind: NotifierKind::Alertmanager,
+            url: Some(url),
+            ..Default::default()
+        };
+        Alertmanager::new(&config).unwrap().notify(&[event()]).unwrap();
+
+        let (path, body) = receiver.recv_timeout(std::time::Duration::from_secs(5)).unwrap();
+        assert_eq!(path, "/api/v2/alerts");
+        let body: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(body[0]["labels"]["host"], "node-1");
+    }
+}
@@ -0,0 +1,172 @@
+/* Email through an SMTP relay. All the events of a tick are sent as one message with a plain text
+    and an HTML part, so several containers failing at once don't flood the mailbox.
+*/
+use super::{Event, Notifier, HOSTNAME};
+use config::{NotifierConfig, SmtpConfig};
+use lettre::smtp::authentication::Credentials;
+use lettre::{ClientSecurity, ClientTlsParameters, SmtpClient, Transport};
+use lettre_email::EmailBuilder;
+use native_tls::TlsConnector;
+use std::fmt::Write;
+use std::time::Duration;
+
+pub struct Email {
+    smtp: SmtpConfig,
+    timeout: Duration,
+}
+
+impl Email {
+    pub fn new(config: &NotifierConfig) -> Result<Self, String> {
+        let smtp = config
+            .smtp
+            .clone()
+            .ok_or_else(|| "`smtp` section is required for the email notifier".to_string())?;
+        if smtp.to.is_empty() {
+            return Err("`smtp.to` must contain at least one recipient".to_string());
+        }
+        if !smtp.starttls && (smtp.username.is_some() || smtp.password.is_some()) {
+            return Err("`smtp.starttls` must be enabled to send the credentials".to_string());
+        }
+        Ok(Self {
+            smtp,
+            timeout: config.timeout,
+        })
+    }
+
+    fn client(&self) -> Result<SmtpClient, String> {
+        let security = if self.smtp.starttls {
+            let connector = TlsConnector::new().map_err(|e| format!("Cannot create TLS connector: {}", e))?;
+            ClientSecurity::Required(ClientTlsParameters::new(self.smtp.host.clone(), connector))
+        } else {
+            ClientSecurity::None
+        };
+        let mut client = SmtpClient::new((self.smtp.host.as_str(), self.smtp.port), security)
+            .map_err(|e| format!("Cannot connect to {}:{}: {}", self.smtp.host, self.smtp.port, e))?
+            .timeout(Some(self.timeout));
+        if let (Some(username), Some(password)) = (&self.smtp.username, &self.smtp.password) {
+            client = client.credentials(Credentials::new(username.clone(), password.clone()));
+        }
+        Ok(client)
+    }
+}
+
+pub(crate) fn subject(events: &[Event]) -> String {
+    match events {
+        [event] => format!(
+            "[docker-check] {} {} on {}",
+            event.name.trim_start_matches('/'),
+            event.event.describe(),
+            event.host
+        ),
+        _ => format!("[docker-check] {} container events on {}", events.len(), *HOSTNAME),
+    }
+}
+
+pub(crate) fn text(events: &[Event]) -> String {
+    let mut text = String::new();
+    for event in events {
+        let _ = writeln!(
+            text,
+            "{} {} at {} (image {}, restarts: {})",
+            event.name.trim_start_matches('/'),
+            event.event.describe(),
+            event.time,
+            event.image,
+            event.restarts
+        );
+        if !event.health_log.is_empty() {
+            for line in event.health_log.lines() {
+                let _ = writeln!(text, "    {}", line);
+            }
+        }
+        text.push('\n');
+    }
+    text
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+pub(crate) fn html(events: &[Event]) -> String {
+    let mut html = String::from(
+        "<table border=\"1\" cellpadding=\"4\" style=\"border-collapse: collapse\">\n\
+         <tr><th>Container</th><th>Event</th><th>Time</th><th>Image</th><th>Restarts</th><th>Healthcheck output</th></tr>\n",
+    );
+    for event in events {
+        let _ = writeln!(
+            html,
+            "<tr style=\"color: #{:06X}\"><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td><pre>{}</pre></td></tr>",
+            event.event.color(),
+            escape_html(event.name.trim_start_matches('/')),
+            event.event.describe(),
+            escape_html(&event.time),
+            escape_html(&event.image),
+            event.restarts,
+            escape_html(&event.health_log)
+        );
+    }
+    html.push_str("</table>\n");
+    html
+}
+
+impl Notifier for Email {
+    fn notify(&self, events: &[Event]) -> Result<(), String> {
+        if events.is_empty() {
+            return Ok(());
+        }
+        let mut builder = EmailBuilder::new()
+            .from(self.smtp.from.as_str())
+            .subject(subject(events))
+            .alternative(html(events), text(events));
+        for recipient in &self.smtp.to {
+            builder = builder.to(recipient.as_str());
+        }
+        let email = builder
+            .build()
+            .map_err(|e| format!("Cannot build the message: {}", e))?;
+        self.client()?
+            .transport()
+            .send(email.into())
+            .map(|_| ())
+            .map_err(|e| format!("Cannot send the message through {}: {}", self.smtp.host, e))
+    }
+
+    fn batched(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use notifiers::tests::event;
+    use notifiers::EventKind;
+
+    #[test]
+    fn single_event() {
+        let events = [event()];
+        assert_eq!(subject(&events), "[docker-check] db reached hard failures on node-1");
+        assert_eq!(
+            text(&events),
+            "db reached hard failures at 2019-01-01T00:00:00+00:00 (image postgres:11, restarts: 3)\n    \
+             pg_isready: no response\n\n"
+        );
+    }
+
+    #[test]
+    fn batch_of_events() {
+        let mut restarted = event();
+        restarted.event = EventKind::Restart;
+        restarted.name = "/web<1>".to_string();
+        let events = [event(), restarted];
+        assert!(subject(&events).starts_with("[docker-check] 2 container events on "));
+        let html = html(&events);
+        assert!(html.contains("<td>db</td><td>reached hard failures</td>"));
+        assert!(html.contains("<td>web&lt;1&gt;</td><td>was restarted</td>"));
+        assert_eq!(html.matches("<tr").count(), 3);
+    }
+}
@@ -0,0 +1,171 @@
+/* Payload formats of the webhook notifier, `format` of the notifier:
+    json    - the event as is
+    slack   - Slack incoming webhook message with blocks
+    teams   - Microsoft Teams connector card
+    discord - Discord webhook message with an embed
+*/
+use super::{Event, EventKind};
+use config::MessageFormat;
+use serde_json::{self, Value};
+
+const NO_OUTPUT: &str = "(no output)";
+
+impl EventKind {
+    /// Status colour as RGB
+    pub(crate) fn color(self) -> u32 {
+        match self {
+            EventKind::Unhealthy => 0xFB_8C_00,
+            EventKind::Restart => 0xF9_A8_25,
+            EventKind::HardFailure => 0xD3_2F_2F,
+            EventKind::Recovered => 0x2E_7D_32,
+            EventKind::Disappeared => 0x75_75_75,
+        }
+    }
+
+    pub(crate) fn describe(self) -> &'static str {
+        match self {
+            EventKind::Unhealthy => "became unhealthy",
+            EventKind::Restart => "was restarted",
+            EventKind::HardFailure => "reached hard failures",
+            EventKind::Recovered => "is healthy again",
+            EventKind::Disappeared => "has disappeared",
+        }
+    }
+}
+
+fn title(event: &Event) -> String {
+    format!(
+        "Container {} {} on {}",
+        event.name.trim_start_matches('/'),
+        event.event.describe(),
+        event.host
+    )
+}
+
+fn health_log(event: &Event) -> &str {
+    if event.health_log.is_empty() {
+        NO_OUTPUT
+    } else {
+        &event.health_log
+    }
+}
+
+fn facts(event: &Event) -> Vec<(&'static str, String)> {
+    vec![
+        ("Container", event.name.trim_start_matches('/').to_string()),
+        ("Host", event.host.clone()),
+        ("Image", event.image.clone()),
+        ("Restarts", event.restarts.to_string()),
+    ]
+}
+
+// control characters of Slack's mrkdwn
+fn escape_slack(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+fn slack(event: &Event) -> Value {
+    let fields = facts(event)
+        .into_iter()
+        .map(|(name, value)| json!({"type": "mrkdwn", "text": format!("*{}*\n{}", name, escape_slack(&value))}))
+        .collect::<Vec<_>>();
+    json!({
+        "text": title(event),
+        "attachments": [{
+            "color": format!("#{:06X}", event.event.color()),
+            "blocks": [
+                {"type": "section", "text": {"type": "mrkdwn", "text": format!("*{}*", escape_slack(&title(event)))}},
+                {"type": "section", "fields": fields},
+                {"type": "section", "text": {"type": "mrkdwn", "text": format!("```{}```", escape_slack(health_log(event)))}},
+            ],
+        }],
+    })
+}
+
+fn teams(event: &Event) -> Value {
+    let facts = facts(event)
+        .into_iter()
+        .map(|(name, value)| json!({"name": name, "value": value}))
+        .collect::<Vec<_>>();
+    json!({
+        "@type": "MessageCard",
+        "@context": "https://schema.org/extensions",
+        "themeColor": format!("{:06X}", event.event.color()),
+        "summary": title(event),
+        "title": title(event),
+        "sections": [{
+            "facts": facts,
+            "text": format!("<pre>{}</pre>", health_log(event).replace('<', "&lt;")),
+        }],
+    })
+}
+
+fn discord(event: &Event) -> Value {
+    let fields = facts(event)
+        .into_iter()
+        .map(|(name, value)| json!({"name": name, "value": value, "inline": true}))
+        .collect::<Vec<_>>();
+    json!({
+        "embeds": [{
+            "title": title(event),
+            "color": event.event.color(),
+            "fields": fields,
+            "description": format!("```\n{}\n```", health_log(event)),
+            "timestamp": event.time,
+        }],
+    })
+}
+
+pub fn render(format: MessageFormat, event: &Event) -> Value {
+    match format {
+        MessageFormat::Json => serde_json::to_value(event).unwrap_or_default(),
+        MessageFormat::Slack => slack(event),
+        MessageFormat::Teams => teams(event),
+        MessageFormat::Discord => discord(event),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use notifiers::tests::event;
+
+    #[test]
+    fn json_test() {
+        let payload = render(MessageFormat::Json, &event());
+        assert_eq!(payload["event"], "hard_failure");
+        assert_eq!(payload["health_log"], "pg_isready: no response");
+    }
+
+    #[test]
+    fn slack_test() {
+        let payload = render(MessageFormat::Slack, &event());
+        assert_eq!(payload["text"], "Container db reached hard failures on node-1");
+        let attachment = &payload["attachments"][0];
+        assert_eq!(attachment["color"], "#D32F2F");
+        assert_eq!(attachment["blocks"][1]["fields"][0]["text"], "*Container*\ndb");
+        assert_eq!(attachment["blocks"][2]["text"]["text"], "```pg_isready: no response```");
+    }
+
+    #[test]
+    fn teams_test() {
+        let mut event = event();
+        event.event = EventKind::Restart;
+        event.health_log = String::new();
+        let payload = render(MessageFormat::Teams, &event);
+        assert_eq!(payload["themeColor"], "F9A825");
+        assert_eq!(payload["title"], "Container db was restarted on node-1");
+        assert_eq!(payload["sections"][0]["facts"][3]["value"], "3");
+        assert_eq!(payload["sections"][0]["text"], "<pre>(no output)</pre>");
+    }
+
+    #[test]
+    fn discord_test() {
+        let payload = render(MessageFormat::Discord, &event());
+        let embed = &payload["embeds"][0];
+        assert_eq!(embed["color"], 0xD3_2F_2F);
+        assert_eq!(embed["fields"][1]["name"], "Host");
+        assert_eq!(embed["fields"][1]["value"], "node-1");
+        assert_eq!(embed["timestamp"], "2019-01-01T00:00:00+00:00");
+    }
+}
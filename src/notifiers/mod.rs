@@ -0,0 +1,334 @@
+/* Built-in notifiers, configured as `[[notifiers]]`.
+    The checker collects the events of a tick and hands them over to the dispatcher, every notifier
+    delivers them in its own thread so a slow endpoint doesn't hold up the checker or the other notifiers.
+    Webhook payloads can be formatted for Slack, Teams or Discord, see format.rs.
+    PagerDuty and Alertmanager get an incident/alert on hard failure, which is resolved on recovery.
+    Email is batched: one message per tick.
+    A failed delivery is retried `retries` times, waiting `backoff` before the first retry and twice as long
+    before every next one, see retry.rs. Events that still couldn't be delivered go to the dead-letter log.
+*/
+pub mod alertmanager;
+pub mod email;
+pub mod format;
+pub mod pagerduty;
+pub mod webhook;
+
+use chrono::Utc;
+use config::{DeadLettersConfig, NotifierConfig, NotifierKind};
+use dead_letters::{self, DeadLetter, Target};
+use docker_checker::ContainerStats;
+use dockworker::container::Container;
+use retry::Retry;
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+
+// longer healthcheck output is cut, it's just a hint of what went wrong
+const HEALTH_LOG_EXCERPT: usize = 1000;
+
+lazy_static! {
+    pub static ref HOSTNAME: String = hostname();
+}
+
+fn hostname() -> String {
+    fs::read_to_string("/proc/sys/kernel/hostname")
+        .map(|name| name.trim().to_string())
+        .or_else(|_| env::var("HOSTNAME"))
+        .unwrap_or_else(|_| "unknown".to_string())
+}
+
+/// Container lifecycle transitions, every one can have its own hook and notifiers
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EventKind {
+    // the first failed healthcheck after the container was healthy
+    Unhealthy,
+    // container was restarted after consecutive_failures
+    Restart,
+    // container is healthy again after it was restarted
+    Recovered,
+    // container reached hard_failures
+    HardFailure,
+    // container was destroyed or not seen for `docker.purge_unseen`
+    Disappeared,
+}
+
+impl EventKind {
+    pub const ALL: [EventKind; 5] = [
+        EventKind::Unhealthy,
+        EventKind::Restart,
+        EventKind::Recovered,
+        EventKind::HardFailure,
+        EventKind::Disappeared,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            EventKind::Unhealthy => "unhealthy",
+            EventKind::Restart => "restart",
+            EventKind::Recovered => "recovered",
+            EventKind::HardFailure => "hard_failure",
+            EventKind::Disappeared => "disappeared",
+        }
+    }
+}
+
+/// Payload of every notification
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Event {
+    pub event: EventKind,
+    // RFC 3339
+    pub time: String,
+    pub host: String,
+    pub id: String,
+    pub name: String,
+    pub image: String,
+    pub labels: HashMap<String, String>,
+    // output of the last healthcheck
+    pub health_log: String,
+    pub restarts: u32,
+}
+
+impl Event {
+    pub fn new(event: EventKind, container: &Container, name: &str, health_log: &str, restarts: u32) -> Self {
+        Self {
+            event,
+            time: Utc::now().to_rfc3339(),
+            host: HOSTNAME.clone(),
+            id: container.Id.clone(),
+            name: name.to_string(),
+            image: container.Image.clone(),
+            labels: container.Labels.clone().unwrap_or_default(),
+            health_log: excerpt(health_log),
+            restarts,
+        }
+    }
+
+    /// Event of a container that is known only from the previous checks
+    pub fn from_stats(event: EventKind, id: &str, stats: &ContainerStats) -> Self {
+        Self {
+            event,
+            time: Utc::now().to_rfc3339(),
+            host: HOSTNAME.clone(),
+            id: id.to_string(),
+            name: stats.name.clone(),
+            image: stats.image.clone(),
+            labels: HashMap::new(),
+            health_log: String::new(),
+            restarts: stats.restarts,
+        }
+    }
+}
+
+fn excerpt(output: &str) -> String {
+    let output = output.trim();
+    match output.char_indices().nth(HEALTH_LOG_EXCERPT) {
+        Some((idx, _)) => format!("{}...", &output[..idx]),
+        None => output.to_string(),
+    }
+}
+
+pub trait Notifier: Send {
+    /// Delivers the events, one by one unless the notifier is `batched`
+    fn notify(&self, events: &[Event]) -> Result<(), String>;
+
+    /// Batched notifiers get all the events of a tick at once
+    fn batched(&self) -> bool {
+        false
+    }
+}
+
+pub(crate) fn build(config: &NotifierConfig) -> Result<Box<dyn Notifier>, String> {
+    match config.kind {
+        NotifierKind::Webhook => Ok(Box::new(webhook::Webhook::new(config)?)),
+        NotifierKind::Pagerduty => Ok(Box::new(pagerduty::PagerDuty::new(config)?)),
+        NotifierKind::Alertmanager => Ok(Box::new(alertmanager::Alertmanager::new(config)?)),
+        NotifierKind::Email => Ok(Box::new(email::Email::new(config)?)),
+    }
+}
+
+/// Name of the notifier in logs and dead letters
+pub(crate) fn notifier_name(idx: usize, config: &NotifierConfig) -> String {
+    config.name.clone().unwrap_or_else(|| format!("notifiers[{}]", idx))
+}
+
+/// Tries to deliver the events `retries + 1` times, returns the last error if none of the attempts succeeded
+pub(crate) fn deliver(notifier: &dyn Notifier, events: &[Event], retry: &Retry) -> Result<(), String> {
+    retry.run(|| notifier.notify(events), |_| true)
+}
+
+fn run_worker(
+    name: String,
+    config: NotifierConfig,
+    notifier: Box<dyn Notifier>,
+    receiver: Receiver<Arc<Vec<Event>>>,
+    dead_letters: DeadLettersConfig,
+) {
+    let retry = Retry::new(config.retries, config.backoff, config.jitter);
+    for events in receiver {
+        let events = events
+            .iter()
+            .filter(|event| config.events.contains(&event.event))
+            .cloned()
+            .collect::<Vec<_>>();
+        if events.is_empty() {
+            continue;
+        }
+        let batches = if notifier.batched() {
+            vec![&events[..]]
+        } else {
+            events.chunks(1).collect()
+        };
+        for batch in batches {
+            match deliver(notifier.as_ref(), batch, &retry) {
+                Ok(()) => debug!("Notifier {} delivered {} event(s)", name, batch.len()),
+                Err(e) => {
+                    error!(
+                        "Notifier {} failed to deliver {} event(s) after {} retries: {}",
+                        name,
+                        batch.len(),
+                        config.retries,
+                        e
+                    );
+                    let target = Target::Notifier { name: name.clone() };
+                    dead_letters::store(&dead_letters, DeadLetter::new(target, &e, batch.to_vec()));
+                }
+            }
+        }
+    }
+}
+
+/// Collects the events of a tick and passes them to the notifier threads on `flush`
+#[derive(Default)]
+pub struct Dispatcher {
+    senders: Vec<Sender<Arc<Vec<Event>>>>,
+    workers: Vec<JoinHandle<()>>,
+    pending: Vec<Event>,
+}
+
+impl Dispatcher {
+    /// Starts a thread for every notifier. The threads exit when the dispatcher is dropped.
+    pub fn new(configs: &[NotifierConfig], dead_letters: &DeadLettersConfig) -> Result<Self, String> {
+        let mut senders = Vec::new();
+        let mut workers = Vec::new();
+        for (idx, config) in configs.iter().enumerate() {
+            let name = notifier_name(idx, config);
+            let notifier = build(config).map_err(|e| format!("Cannot create notifier {}: {}", name, e))?;
+            let (sender, receiver) = mpsc::channel();
+            let config = config.clone();
+            let dead_letters = dead_letters.clone();
+            workers.push(thread::spawn(move || {
+                run_worker(name, config, notifier, receiver, dead_letters)
+            }));
+            senders.push(sender);
+        }
+        Ok(Self {
+            senders,
+            workers,
+            pending: Vec::new(),
+        })
+    }
+
+    pub fn push(&mut self, event: Event) {
+        if !self.senders.is_empty() {
+            self.pending.push(event);
+        }
+    }
+
+    /// Hands over the collected events to the notifiers
+    pub fn flush(&mut self) {
+        if self.pending.is_empty() {
+            return;
+        }
+        let events = Arc::new(self.pending.drain(..).collect::<Vec<_>>());
+        for sender in &self.senders {
+            if sender.send(events.clone()).is_err() {
+                error!("Notifier thread has stopped, {} event(s) are lost", events.len());
+            }
+        }
+    }
+
+    /// Flushes the pending events and waits until the notifiers have delivered everything
+    pub fn shutdown(mut self) {
+        self.flush();
+        self.senders.clear();
+        for worker in self.workers.drain(..) {
+            if worker.join().is_err() {
+                error!("Notifier thread has panicked");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    pub(crate) fn event() -> Event {
+        let mut labels = HashMap::new();
+        labels.insert("com.docker.compose.service".to_string(), "db".to_string());
+        Event {
+            event: EventKind::HardFailure,
+            time: "2019-01-01T00:00:00+00:00".to_string(),
+            host: "node-1".to_string(),
+            id: "0123abcd".to_string(),
+            name: "/db".to_string(),
+            image: "postgres:11".to_string(),
+            labels,
+            health_log: "pg_isready: no response".to_string(),
+            restarts: 3,
+        }
+    }
+
+    // fails the first `failures` attempts
+    struct Flaky {
+        failures: usize,
+        attempts: AtomicUsize,
+    }
+
+    impl Notifier for Flaky {
+        fn notify(&self, _events: &[Event]) -> Result<(), String> {
+            let attempt = self.attempts.fetch_add(1, Ordering::SeqCst) + 1;
+            if attempt <= self.failures {
+                Err(format!("attempt {} failed", attempt))
+            } else {
+                Ok(())
+            }
+        }
+    }
+
+    #[test]
+    fn deliver_retries() {
+        let flaky = Flaky {
+            failures: 2,
+            attempts: AtomicUsize::new(0),
+        };
+        assert!(deliver(&flaky, &[], &Retry::new(2, Duration::from_millis(1), 0.0)).is_ok());
+        assert_eq!(flaky.attempts.load(Ordering::SeqCst), 3);
+
+        let flaky = Flaky {
+            failures: 5,
+            attempts: AtomicUsize::new(0),
+        };
+        assert_eq!(
+            deliver(&flaky, &[], &Retry::new(1, Duration::from_millis(1), 0.0)).unwrap_err(),
+            "attempt 2 failed"
+        );
+        assert_eq!(flaky.attempts.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn excerpt_test() {
+        assert_eq!(
+            excerpt("  curl: (7) Failed to connect\n"),
+            "curl: (7) Failed to connect"
+        );
+        let long = "ж".repeat(HEALTH_LOG_EXCERPT + 10);
+        assert_eq!(excerpt(&long).chars().count(), HEALTH_LOG_EXCERPT + 3);
+    }
+}
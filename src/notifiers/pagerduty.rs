@@ -0,0 +1,122 @@
+/* PagerDuty Events API v2: an incident is triggered when a container reaches hard failures
+    and resolved when it's healthy again or gone. The dedup key is derived from the host and the container name,
+    so the incident survives container re-creation.
+*/
+use super::webhook::JsonEndpoint;
+use super::{Event, EventKind, Notifier};
+use config::NotifierConfig;
+use serde_json::{self, Value};
+
+const EVENTS_URL: &str = "https://events.pagerduty.com/v2/enqueue";
+
+pub struct PagerDuty {
+    endpoint: JsonEndpoint,
+    routing_key: String,
+}
+
+pub(crate) fn dedup_key(event: &Event) -> String {
+    format!("docker-check/{}/{}", event.host, event.name.trim_start_matches('/'))
+}
+
+impl PagerDuty {
+    pub fn new(config: &NotifierConfig) -> Result<Self, String> {
+        let routing_key = config
+            .routing_key
+            .clone()
+            .ok_or_else(|| "`routing_key` is required for the pagerduty notifier".to_string())?;
+        let url = config.url.clone().unwrap_or_else(|| EVENTS_URL.to_string());
+        Ok(Self {
+            endpoint: JsonEndpoint::new(url, config)?,
+            routing_key,
+        })
+    }
+
+    /// Events API document for the event, None for the events that don't change the incident
+    pub(crate) fn document(&self, event: &Event) -> Option<Value> {
+        match event.event {
+            EventKind::HardFailure => Some(json!({
+                "routing_key": self.routing_key,
+                "event_action": "trigger",
+                "dedup_key": dedup_key(event),
+                "payload": {
+                    "summary": format!(
+                        "Container {} reached hard failures on {}",
+                        event.name.trim_start_matches('/'),
+                        event.host
+                    ),
+                    "source": event.host,
+                    "severity": "critical",
+                    "timestamp": event.time,
+                    "component": event.name.trim_start_matches('/'),
+                    "custom_details": serde_json::to_value(event).unwrap_or_default(),
+                },
+            })),
+            // a removed container never recovers
+            EventKind::Recovered | EventKind::Disappeared => Some(json!({
+                "routing_key": self.routing_key,
+                "event_action": "resolve",
+                "dedup_key": dedup_key(event),
+            })),
+            _ => None,
+        }
+    }
+}
+
+impl Notifier for PagerDuty {
+    fn notify(&self, events: &[Event]) -> Result<(), String> {
+        for document in events.iter().filter_map(|event| self.document(event)) {
+            self.endpoint.post(&document)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use config::NotifierKind;
+    use notifiers::tests::event;
+
+    fn pagerduty() -> PagerDuty {
+        PagerDuty::new(&NotifierConfig {
+            kind: NotifierKind::Pagerduty,
+            routing_key: Some("R0UT1NG".to_string()),
+            ..Default::default()
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn trigger_and_resolve() {
+        let pagerduty = pagerduty();
+        assert_eq!(pagerduty.endpoint.url, EVENTS_URL);
+
+        let mut event = event();
+        let trigger = pagerduty.document(&event).unwrap();
+        assert_eq!(trigger["event_action"], "trigger");
+        assert_eq!(trigger["dedup_key"], "docker-check/node-1/db");
+        assert_eq!(trigger["payload"]["source"], "node-1");
+        assert_eq!(trigger["payload"]["custom_details"]["restarts"], 3);
+
+        event.event = EventKind::Recovered;
+        let resolve = pagerduty.document(&event).unwrap();
+        assert_eq!(
+            resolve,
+            json!({"routing_key": "R0UT1NG", "event_action": "resolve", "dedup_key": "docker-check/node-1/db"})
+        );
+        event.event = EventKind::Disappeared;
+        assert_eq!(pagerduty.document(&event), Some(resolve));
+
+        event.event = EventKind::Restart;
+        assert_eq!(pagerduty.document(&event), None);
+    }
+
+    #[test]
+    fn routing_key_is_required() {
+        let config = NotifierConfig {
+            kind: NotifierKind::Pagerduty,
+            ..Default::default()
+        };
+        assert!(PagerDuty::new(&config).is_err());
+    }
+}
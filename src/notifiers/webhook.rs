@@ -0,0 +1,172 @@
+/* Generic webhook: POSTs every event as a JSON document to `url` with the configured headers.
+    The document is the event itself or a chat message, depending on `format`.
+*/
+use super::{format, Event, Notifier};
+use config::{MessageFormat, NotifierConfig};
+use reqwest::Client;
+use serde_json::Value;
+
+/// HTTP endpoint that accepts JSON documents, shared by the notifiers that talk HTTP
+pub(crate) struct JsonEndpoint {
+    client: Client,
+    pub url: String,
+    headers: Vec<(String, String)>,
+}
+
+impl JsonEndpoint {
+    /// Uses the headers and timeout of the notifier config
+    pub fn new(url: String, config: &NotifierConfig) -> Result<Self, String> {
+        let client = Client::builder()
+            .timeout(config.timeout)
+            .build()
+            .map_err(|e| format!("Cannot create HTTP client: {}", e))?;
+        Ok(Self {
+            client,
+            url,
+            headers: config
+                .headers
+                .iter()
+                .map(|(name, value)| (name.clone(), value.clone()))
+                .collect(),
+        })
+    }
+
+    pub fn post(&self, document: &Value) -> Result<(), String> {
+        let mut request = self.client.post(&self.url).json(document);
+        for (name, value) in &self.headers {
+            request = request.header(name.as_str(), value.as_str());
+        }
+        let response = request
+            .send()
+            .map_err(|e| format!("POST to {} failed: {}", self.url, e))?;
+        if !response.status().is_success() {
+            return Err(format!("{} responded with {}", self.url, response.status()));
+        }
+        Ok(())
+    }
+}
+
+pub struct Webhook {
+    endpoint: JsonEndpoint,
+    format: MessageFormat,
+}
+
+impl Webhook {
+    pub fn new(config: &NotifierConfig) -> Result<Self, String> {
+        let url = config
+            .url
+            .clone()
+            .ok_or_else(|| "`url` is required for the webhook notifier".to_string())?;
+        Ok(Self {
+            endpoint: JsonEndpoint::new(url, config)?,
+            format: config.format,
+        })
+    }
+}
+
+impl Notifier for Webhook {
+    fn notify(&self, events: &[Event]) -> Result<(), String> {
+        for event in events {
+            self.endpoint.post(&format::render(self.format, event))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use config::NotifierKind;
+    use notifiers::tests::event;
+    use serde_json;
+    use std::collections::HashMap;
+    use std::io::Read;
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::Duration;
+    use tiny_http::{Response, Server};
+
+    #[test]
+    fn webhook_posts_json() {
+        let server = Server::http("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/hook", server.server_addr());
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            let mut request = server.recv().unwrap();
+            let token = request
+                .headers()
+                .iter()
+                .find(|header| header.field.as_str().as_str().eq_ignore_ascii_case("X-Token"))
+                .map(|header| header.value.as_str().to_string());
+            let mut body = String::new();
+            request.as_reader().read_to_string(&mut body).unwrap();
+            request.respond(Response::from_string("ok")).unwrap();
+            sender.send((token, body)).unwrap();
+        });
+
+        let mut headers = HashMap::new();
+        headers.insert("X-Token".to_string(), "secret".to_string());
+        let config = NotifierConfig {
+            kind: NotifierKind::Webhook,
+            url: Some(url),
+            headers,
+            ..Default::default()
+        };
+        Webhook::new(&config).unwrap().notify(&[event()]).unwrap();
+
+        let (token, body) = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(token, Some("secret".to_string()));
+        let body: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(body["event"], "hard_failure");
+        assert_eq!(body["name"], "/db");
+        assert_eq!(body["labels"]["com.docker.compose.service"], "db");
+        assert_eq!(body["restarts"], 3);
+    }
+
+    #[test]
+    fn webhook_posts_formatted_message() {
+        let server = Server::http("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/hook", server.server_addr());
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            let mut request = server.recv().unwrap();
+            let mut body = String::new();
+            request.as_reader().read_to_string(&mut body).unwrap();
+            request.respond(Response::from_string("ok")).unwrap();
+            sender.send(body).unwrap();
+        });
+
+        let config = NotifierConfig {
+            kind: NotifierKind::Webhook,
+            url: Some(url),
+            format: MessageFormat::Discord,
+            ..Default::default()
+        };
+        Webhook::new(&config).unwrap().notify(&[event()]).unwrap();
+
+        let body: Value = serde_json::from_str(&receiver.recv_timeout(Duration::from_secs(5)).unwrap()).unwrap();
+        assert_eq!(
+            body["embeds"][0]["title"],
+            "Container db reached hard failures on node-1"
+        );
+    }
+
+    #[test]
+    fn webhook_fails_on_error_status() {
+        let server = Server::http("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/hook", server.server_addr());
+        thread::spawn(move || {
+            let request = server.recv().unwrap();
+            request
+                .respond(Response::from_string("nope").with_status_code(500))
+                .unwrap();
+        });
+        let config = NotifierConfig {
+            kind: NotifierKind::Webhook,
+            url: Some(url),
+            ..Default::default()
+        };
+        let error = Webhook::new(&config).unwrap().notify(&[event()]).unwrap_err();
+        assert!(error.contains("500"), "{}", error);
+    }
+}
@@ -0,0 +1,247 @@
+use config::{self, Config, HooksConfig, PolicyConfig};
+use hooks::Hook;
+use notifiers::EventKind;
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::time::Duration;
+
+// Labels that allow containers to override the policy from the config
+pub const CONSECUTIVE_FAILURES_LABEL: &str = "docker-check.consecutive_failures";
+pub const HARD_FAILURES_LABEL: &str = "docker-check.hard_failures";
+pub const ON_FAILURE_LABEL: &str = "docker-check.on_failure";
+pub const STOP_TIMEOUT_LABEL: &str = "docker-check.stop_timeout";
+pub const CRITICAL_LABEL: &str = "docker-check.critical";
+pub const ON_UNHEALTHY_LABEL: &str = "docker-check.on_unhealthy";
+pub const ON_RESTART_LABEL: &str = "docker-check.on_restart";
+pub const ON_RECOVERED_LABEL: &str = "docker-check.on_recovered";
+pub const ON_HARD_FAILURE_LABEL: &str = "docker-check.on_hard_failure";
+pub const ON_DISAPPEARED_LABEL: &str = "docker-check.on_disappeared";
+pub const HOOK_TIMEOUT_LABEL: &str = "docker-check.hook_timeout";
+
+const HOOK_LABELS: [(&str, EventKind); 5] = [
+    (ON_UNHEALTHY_LABEL, EventKind::Unhealthy),
+    (ON_RESTART_LABEL, EventKind::Restart),
+    (ON_RECOVERED_LABEL, EventKind::Recovered),
+    (ON_HARD_FAILURE_LABEL, EventKind::HardFailure),
+    (ON_DISAPPEARED_LABEL, EventKind::Disappeared),
+];
+
+/// Which policy a container has matched: top-level `[containers]` one or a `[[containers.policy]]` group by index
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PolicyRef {
+    Default,
+    Group(usize),
+}
+
+/// Effective policy for a single container: thresholds and actions after applying label overrides
+#[derive(Debug, Clone, PartialEq)]
+pub struct Policy {
+    pub consecutive_failures: u16,
+    pub hard_failures: u16,
+    pub run_on_failure: String,
+    pub stop_timeout: Duration,
+    // failing critical container makes the whole node unhealthy
+    pub critical: bool,
+    pub hooks: HooksConfig,
+    pub hook_timeout: Duration,
+}
+
+impl Policy {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            consecutive_failures: config.containers.consecutive_failures,
+            hard_failures: config.containers.hard_failures,
+            run_on_failure: config.containers.run_on_failure.clone(),
+            stop_timeout: config.docker.stop_timeout,
+            critical: config.containers.critical,
+            hooks: config.containers.hooks.clone(),
+            hook_timeout: config.containers.hooks.timeout.unwrap_or(config.hooks.timeout),
+        }
+    }
+
+    /// Hook of the lifecycle event, hard failures fall back to `run_on_failure`
+    pub fn hook(&self, event: EventKind) -> Option<Hook> {
+        let template = match (event, self.hooks.get(event)) {
+            (_, Some(template)) => template,
+            (EventKind::HardFailure, None) => self.run_on_failure.as_str(),
+            (_, None) => return None,
+        };
+        Some(Hook {
+            template: template.to_string(),
+            timeout: self.hook_timeout,
+        })
+    }
+
+    /// Policy from config, overridden by the matched policy group (if any) and then by the container labels.
+    /// Invalid label values are reported and ignored.
+    pub fn resolve(
+        config: &Config,
+        policy_ref: PolicyRef,
+        container_id: &str,
+        labels: Option<&HashMap<String, String>>,
+    ) -> Self {
+        let mut policy = Self::from_config(config);
+        if let PolicyRef::Group(idx) = policy_ref {
+            if let Some(group) = config.containers.policy.get(idx) {
+                policy.apply_group(group);
+            }
+        }
+        let labels = match labels {
+            Some(labels) => labels,
+            None => return policy,
+        };
+        for (name, value) in labels.iter() {
+            if let Some(&(_, event)) = HOOK_LABELS.iter().find(|&&(label, _)| label == name.as_str()) {
+                *policy.hooks.get_mut(event) = Some(value.clone());
+                continue;
+            }
+            let result = match name.as_str() {
+                CONSECUTIVE_FAILURES_LABEL => parse_number(value).map(|v| policy.consecutive_failures = v),
+                HARD_FAILURES_LABEL => parse_number(value).map(|v| policy.hard_failures = v),
+                ON_FAILURE_LABEL => {
+                    policy.run_on_failure = value.clone();
+                    Ok(())
+                }
+                STOP_TIMEOUT_LABEL => config::parse_duration(value).map(|v| policy.stop_timeout = v),
+                HOOK_TIMEOUT_LABEL => config::parse_duration(value).map(|v| policy.hook_timeout = v),
+                CRITICAL_LABEL => bool::from_str(value.trim())
+                    .map(|v| policy.critical = v)
+                    .map_err(|e| format!("\"{}\" is not a valid boolean: {}", value, e)),
+                _ => Ok(()),
+            };
+            if let Err(e) = result {
+                warn!(
+                    "Container {} has invalid {} label: {}. Using value from config",
+                    container_id, name, e
+                );
+            }
+        }
+        policy
+    }
+
+    fn apply_group(&mut self, group: &PolicyConfig) {
+        if let Some(v) = group.consecutive_failures {
+            self.consecutive_failures = v;
+        }
+        if let Some(v) = group.hard_failures {
+            self.hard_failures = v;
+        }
+        if let Some(ref v) = group.run_on_failure {
+            self.run_on_failure = v.clone();
+        }
+        if let Some(v) = group.stop_timeout {
+            self.stop_timeout = v;
+        }
+        if let Some(v) = group.critical {
+            self.critical = v;
+        }
+        if let Some(v) = group.hooks.timeout {
+            self.hook_timeout = v;
+        }
+        for &event in EventKind::ALL.iter() {
+            if let Some(v) = group.hooks.get(event) {
+                *self.hooks.get_mut(event) = Some(v.to_string());
+            }
+        }
+    }
+}
+
+fn parse_number(value: &str) -> Result<u16, String> {
+    u16::from_str(value.trim()).map_err(|e| format!("\"{}\" is not a valid number: {}", value, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolve_without_labels() {
+        let settings = config::get_settings("tests/settings").unwrap();
+        let policy = Policy::resolve(&settings, PolicyRef::Default, "dfdb8ee577c1", None);
+        assert_eq!(policy, Policy::from_config(&settings));
+        assert_eq!(policy.consecutive_failures, 5);
+        assert_eq!(policy.hard_failures, 3);
+    }
+
+    #[test]
+    fn resolve_with_labels() {
+        let settings = config::get_settings("tests/settings").unwrap();
+        let mut labels = HashMap::new();
+        labels.insert(CONSECUTIVE_FAILURES_LABEL.to_string(), "10".to_string());
+        labels.insert(HARD_FAILURES_LABEL.to_string(), "1".to_string());
+        labels.insert(ON_FAILURE_LABEL.to_string(), "/path/hook".to_string());
+        labels.insert(STOP_TIMEOUT_LABEL.to_string(), "1m".to_string());
+        labels.insert(CRITICAL_LABEL.to_string(), "true".to_string());
+        labels.insert("com.docker.compose.service".to_string(), "web".to_string());
+        let policy = Policy::resolve(&settings, PolicyRef::Default, "dfdb8ee577c1", Some(&labels));
+        assert_eq!(policy.consecutive_failures, 10);
+        assert_eq!(policy.hard_failures, 1);
+        assert_eq!(policy.run_on_failure, "/path/hook");
+        assert_eq!(policy.stop_timeout, Duration::from_secs(60));
+        assert!(policy.critical);
+    }
+
+    #[test]
+    fn invalid_labels_are_ignored() {
+        let settings = config::get_settings("tests/settings").unwrap();
+        let mut labels = HashMap::new();
+        labels.insert(CONSECUTIVE_FAILURES_LABEL.to_string(), "ten".to_string());
+        labels.insert(STOP_TIMEOUT_LABEL.to_string(), "-1".to_string());
+        let policy = Policy::resolve(&settings, PolicyRef::Default, "dfdb8ee577c1", Some(&labels));
+        assert_eq!(policy, Policy::from_config(&settings));
+    }
+
+    #[test]
+    fn resolve_with_group() {
+        let settings = config::get_settings("tests/settings").unwrap();
+        let mut labels = HashMap::new();
+        labels.insert(HARD_FAILURES_LABEL.to_string(), "2".to_string());
+        let policy = Policy::resolve(&settings, PolicyRef::Group(0), "dfdb8ee577c1", Some(&labels));
+        assert_eq!(policy.consecutive_failures, 10);
+        // labels take a priority over the group
+        assert_eq!(policy.hard_failures, 2);
+        // not set in the group
+        assert_eq!(policy.run_on_failure, settings.containers.run_on_failure);
+        assert_eq!(policy.stop_timeout, Duration::from_secs(60));
+    }
+
+    #[test]
+    fn hooks_test() {
+        let settings = config::get_settings("tests/settings").unwrap();
+        let mut labels = HashMap::new();
+        labels.insert(ON_RECOVERED_LABEL.to_string(), "/path/recovered".to_string());
+        let policy = Policy::resolve(&settings, PolicyRef::Group(0), "dfdb8ee577c1", Some(&labels));
+        let template = |event| policy.hook(event).map(|hook| hook.template);
+        // from the group
+        assert_eq!(
+            template(EventKind::Unhealthy),
+            Some("example/notify-slack.sh".to_string())
+        );
+        assert_eq!(
+            policy.hook(EventKind::Unhealthy).unwrap().timeout,
+            Duration::from_secs(120)
+        );
+        // from [containers.hooks]
+        assert_eq!(
+            template(EventKind::Restart),
+            Some("example/notify-slack.sh".to_string())
+        );
+        assert_eq!(template(EventKind::Recovered), Some("/path/recovered".to_string()));
+        assert_eq!(
+            template(EventKind::HardFailure),
+            Some(settings.containers.run_on_failure.clone())
+        );
+
+        labels.insert(ON_HARD_FAILURE_LABEL.to_string(), "/path/hard-failure".to_string());
+        labels.insert(HOOK_TIMEOUT_LABEL.to_string(), "5s".to_string());
+        let policy = Policy::resolve(&settings, PolicyRef::Default, "dfdb8ee577c1", Some(&labels));
+        assert_eq!(policy.hook(EventKind::Unhealthy), None);
+        assert_eq!(
+            policy.hook(EventKind::HardFailure),
+            Some(Hook {
+                template: "/path/hard-failure".to_string(),
+                timeout: Duration::from_secs(5),
+            })
+        );
+    }
+}
@@ -0,0 +1,130 @@
+/* Config reloading: on SIGHUP and, optionally, when the config file changes.
+    The new config is validated and loaded here, the checker swaps it in between ticks.
+*/
+use config::Config;
+use signal_hook;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::SystemTime;
+use validate;
+
+// extensions the `config` crate looks for when the file name is given without one
+const CONFIG_EXTENSIONS: [&str; 6] = ["toml", "json", "yaml", "yml", "hjson", "ini"];
+
+pub struct Reloader {
+    filename: String,
+    loader: fn(&str) -> Result<Config, String>,
+    requested: Arc<AtomicBool>,
+    // watched file and its last seen modification time, if file watching is enabled
+    watched: Option<(PathBuf, Option<SystemTime>)>,
+}
+
+impl Reloader {
+    /// `loader` is used to read the config after it passed validation
+    pub fn new(filename: &str, watch_file: bool, loader: fn(&str) -> Result<Config, String>) -> Result<Self, String> {
+        let requested = Arc::new(AtomicBool::new(false));
+        signal_hook::flag::register(signal_hook::SIGHUP, requested.clone())
+            .map_err(|e| format!("Cannot register SIGHUP handler: {}", e))?;
+        let watched = if watch_file {
+            let path =
+                find_config_file(filename).ok_or_else(|| format!("Cannot find config file {} to watch", filename))?;
+            let modified = modified(&path);
+            Some((path, modified))
+        } else {
+            None
+        };
+        Ok(Self {
+            filename: filename.to_string(),
+            loader,
+            requested,
+            watched,
+        })
+    }
+
+    pub fn request(&self) {
+        self.requested.store(true, Ordering::SeqCst);
+    }
+
+    fn file_changed(&mut self) -> bool {
+        match self.watched {
+            Some((ref path, ref mut last_modified)) => {
+                let current = modified(path);
+                if current != *last_modified {
+                    *last_modified = current;
+                    true
+                } else {
+                    false
+                }
+            }
+            None => false,
+        }
+    }
+
+    /// Returns the new config if reload was requested since the last call
+    pub fn poll(&mut self) -> Option<Result<Config, String>> {
+        let changed = self.file_changed();
+        if !self.requested.swap(false, Ordering::SeqCst) && !changed {
+            return None;
+        }
+        info!("Reloading config from {}", &self.filename);
+        let problems = validate::validate(&self.filename);
+        if !problems.is_empty() {
+            let problems = problems.iter().map(|p| p.to_string()).collect::<Vec<_>>();
+            return Some(Err(format!("New config is invalid: {}", problems.join("; "))));
+        }
+        Some((self.loader)(&self.filename))
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    path.metadata().and_then(|m| m.modified()).ok()
+}
+
+/// Path of the config file, the name can be given with or without extension (like `config::File::with_name`)
+pub(crate) fn find_config_file(filename: &str) -> Option<PathBuf> {
+    let path = PathBuf::from(filename);
+    if path.is_file() {
+        return Some(path);
+    }
+    CONFIG_EXTENSIONS
+        .iter()
+        .map(|ext| path.with_extension(ext))
+        .find(|candidate| candidate.is_file())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use config;
+
+    #[test]
+    fn find_config_file_test() {
+        assert_eq!(
+            find_config_file("tests/settings"),
+            Some(PathBuf::from("tests/settings.toml"))
+        );
+        assert_eq!(
+            find_config_file("tests/settings.toml"),
+            Some(PathBuf::from("tests/settings.toml"))
+        );
+        assert_eq!(find_config_file("tests/no-such-settings"), None);
+    }
+
+    #[test]
+    fn poll_only_when_requested() {
+        let mut reloader = Reloader::new("tests/settings", true, config::get_settings).unwrap();
+        assert!(reloader.poll().is_none());
+        reloader.request();
+        assert!(reloader.poll().unwrap().is_ok());
+        assert!(reloader.poll().is_none());
+    }
+
+    #[test]
+    fn invalid_config_is_rejected() {
+        let mut reloader = Reloader::new("tests/invalid_settings", false, config::get_settings).unwrap();
+        reloader.request();
+        let error = reloader.poll().unwrap().unwrap_err();
+        assert!(error.contains("containers.filter_by"), "{}", error);
+    }
+}
@@ -0,0 +1,105 @@
+/* Retry policy of the notifiers and the hooks: `retries` more attempts after the first one,
+    waiting `backoff` before the first retry and twice as long before every next one.
+    Every delay is randomly stretched or shrunk by up to `jitter` (0.2 is ±20%),
+    so several checkers that failed at the same moment don't retry in lockstep.
+*/
+use rand::{self, Rng};
+use std::fmt::Display;
+use std::thread;
+use std::time::Duration;
+
+// 2^16 * backoff is more than enough, the exponent is capped so the delay can't overflow
+const MAX_DOUBLINGS: u32 = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Retry {
+    pub retries: u32,
+    pub backoff: Duration,
+    pub jitter: f64,
+}
+
+impl Retry {
+    pub fn new(retries: u32, backoff: Duration, jitter: f64) -> Self {
+        Self {
+            retries,
+            backoff,
+            jitter: jitter.max(0.0).min(1.0),
+        }
+    }
+
+    /// Delay before the retry number `retry`, starting from 1
+    pub fn delay(&self, retry: u32) -> Duration {
+        let delay = self.backoff * 2u32.pow(retry.saturating_sub(1).min(MAX_DOUBLINGS));
+        if self.jitter == 0.0 {
+            return delay;
+        }
+        let factor = 1.0 + rand::thread_rng().gen_range(-self.jitter, self.jitter);
+        delay.mul_f64(factor)
+    }
+
+    /// Calls `f` until it succeeds, the retries are exhausted or it fails with an error that isn't `retryable`.
+    /// Returns the last error.
+    pub fn run<T, E, F, P>(&self, mut f: F, retryable: P) -> Result<T, E>
+    where
+        E: Display,
+        F: FnMut() -> Result<T, E>,
+        P: Fn(&E) -> bool,
+    {
+        let mut retry = 0;
+        loop {
+            match f() {
+                Ok(value) => return Ok(value),
+                Err(e) if retry < self.retries && retryable(&e) => {
+                    retry += 1;
+                    let delay = self.delay(retry);
+                    debug!("Attempt failed: {}. Retry {}/{} in {:?}", e, retry, self.retries, delay);
+                    thread::sleep(delay);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exponential_backoff() {
+        let retry = Retry::new(5, Duration::from_millis(100), 0.0);
+        assert_eq!(retry.delay(1), Duration::from_millis(100));
+        assert_eq!(retry.delay(2), Duration::from_millis(200));
+        assert_eq!(retry.delay(4), Duration::from_millis(800));
+        assert_eq!(retry.delay(100), Duration::from_millis(100) * 2u32.pow(MAX_DOUBLINGS));
+    }
+
+    #[test]
+    fn jitter_test() {
+        let retry = Retry::new(5, Duration::from_secs(10), 0.2);
+        for _ in 0..100 {
+            let delay = retry.delay(1);
+            assert!(
+                delay >= Duration::from_secs(8) && delay <= Duration::from_secs(12),
+                "{:?}",
+                delay
+            );
+        }
+        assert_eq!(Retry::new(1, Duration::from_secs(1), 3.0).jitter, 1.0);
+    }
+
+    #[test]
+    fn permanent_errors_are_not_retried() {
+        let retry = Retry::new(3, Duration::from_millis(1), 0.0);
+        let mut attempts = 0;
+        let result: Result<(), String> = retry.run(
+            || {
+                attempts += 1;
+                Err(format!("attempt {} failed", attempts))
+            },
+            |e| !e.starts_with("attempt 2"),
+        );
+        assert_eq!(result.unwrap_err(), "attempt 2 failed");
+        assert_eq!(attempts, 2);
+    }
+}
@@ -1,10 +1,19 @@
+#[cfg(unix)]
+use libc;
 use os_pipe::pipe;
+use std::fmt;
 use std::io;
 use std::io::prelude::*;
 use std::process::ExitStatus;
-use std::process::{Command, Output, Stdio};
+use std::process::{Child, Command, Output, Stdio};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
 
-type CommandResult = io::Result<CommandOutput>;
+// how often a child that has closed its output is checked for exit
+const EXIT_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+pub type CommandResult = Result<CommandOutput, HookError>;
 
 #[derive(Debug)]
 pub struct CommandOutput {
@@ -12,65 +21,255 @@ pub struct CommandOutput {
     pub status: ExitStatus,
 }
 
-pub fn run_command_windows(cmd: &str, args: &Vec<String>) -> CommandResult {
+/// Why the hook didn't succeed. The output is kept whenever the hook has produced one.
+#[derive(Debug)]
+pub enum HookError {
+    // the command doesn't exist, or the pipes for it couldn't be created
+    SpawnFailed(io::Error),
+    NotExecutable(String),
+    // the output couldn't be read
+    Io(io::Error),
+    TimedOut(Duration),
+    // exited with zero status, but the output was mangled, `output` is decoded lossily
+    NonUtf8 { output: String },
+    ExitCode { code: i32, output: String },
+    Signal { signal: i32, output: String },
+}
+
+impl HookError {
+    /// Status for the hook executions metric: the exit code, "signal", "timeout" or "error"
+    pub fn status(&self) -> String {
+        match self {
+            HookError::ExitCode { code, .. } => code.to_string(),
+            HookError::Signal { .. } => "signal".to_string(),
+            HookError::TimedOut(_) => "timeout".to_string(),
+            _ => "error".to_string(),
+        }
+    }
+
+    /// Output of the hook, empty if it wasn't started or didn't finish
+    pub fn output(&self) -> &str {
+        match self {
+            HookError::NonUtf8 { output } | HookError::ExitCode { output, .. } | HookError::Signal { output, .. } => {
+                output
+            }
+            _ => "",
+        }
+    }
+
+    /// A missing or broken hook fails the same way on every attempt, anything else may be transient
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            HookError::SpawnFailed(_) | HookError::NotExecutable(_) | HookError::NonUtf8 { .. } => false,
+            _ => true,
+        }
+    }
+}
+
+impl fmt::Display for HookError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HookError::SpawnFailed(e) => write!(f, "cannot be started: {}", e),
+            HookError::NotExecutable(cmd) => write!(f, "\"{}\" is not executable", cmd),
+            HookError::Io(e) => write!(f, "cannot read the output: {}", e),
+            HookError::TimedOut(timeout) => write!(f, "timed out after {:?}, the process group was killed", timeout),
+            HookError::NonUtf8 { .. } => write!(f, "succeeded, but its output is not valid UTF-8"),
+            HookError::ExitCode { code, .. } => write!(f, "exited with code {}", code),
+            HookError::Signal { signal, .. } => write!(f, "was killed by signal {}", signal),
+        }
+    }
+}
+
+fn spawn_error(cmd: &str, e: io::Error) -> HookError {
+    if e.kind() == io::ErrorKind::PermissionDenied {
+        HookError::NotExecutable(cmd.to_string())
+    } else {
+        HookError::SpawnFailed(e)
+    }
+}
+
+/// Turns the raw output and the exit status into the result of the hook.
+/// A failed exit status wins over the output that isn't valid UTF-8, which is then decoded lossily.
+fn finish(output: Vec<u8>, status: ExitStatus) -> CommandResult {
+    let (output, valid_utf8) = match String::from_utf8(output) {
+        Ok(output) => (output, true),
+        Err(e) => (String::from_utf8_lossy(e.as_bytes()).into_owned(), false),
+    };
+    if status.success() {
+        if !valid_utf8 {
+            return Err(HookError::NonUtf8 { output });
+        }
+        return Ok(CommandOutput { output, status });
+    }
+    match status.code() {
+        Some(code) => Err(HookError::ExitCode { code, output }),
+        None => Err(HookError::Signal {
+            signal: signal(status),
+            output,
+        }),
+    }
+}
+
+#[cfg(unix)]
+fn signal(status: ExitStatus) -> i32 {
+    use std::os::unix::process::ExitStatusExt;
+    status.signal().unwrap_or_default()
+}
+
+#[cfg(not(unix))]
+fn signal(_status: ExitStatus) -> i32 {
+    0
+}
+
+/// Writes the input to the stdin of the child in a separate thread, so a child that doesn't read it
+/// (or a large input) can't block reading of its output
+fn feed_stdin(child: &mut Child, input: &[u8]) {
+    if let Some(mut stdin) = child.stdin.take() {
+        let input = input.to_vec();
+        thread::spawn(move || {
+            // the hook is free to ignore its stdin, a broken pipe isn't an error
+            let _ = stdin.write_all(&input);
+        });
+    }
+}
+
+/// Kills the child together with everything it has started
+fn kill_group(child: &mut Child) {
+    #[cfg(unix)]
+    unsafe {
+        // the child is the leader of its own process group, see `run_command_unix`
+        libc::killpg(child.id() as libc::pid_t, libc::SIGKILL);
+    }
+    #[cfg(not(unix))]
+    let _ = child.kill();
+}
+
+// the timeout isn't enforced on windows
+pub fn run_command_windows(
+    cmd: &str,
+    args: &Vec<String>,
+    env: &[(String, String)],
+    input: &[u8],
+    _timeout: Duration,
+) -> CommandResult {
     let stdout = Stdio::piped();
     let stderr = Stdio::piped();
 
-    let h = Command::new(cmd)
-        .stdin(Stdio::null())
+    let mut h = Command::new(cmd)
+        .stdin(Stdio::piped())
         .stdout(stdout)
         .stderr(stderr)
         .args(args)
-        .spawn();
-    h?.wait_with_output().map(|output: Output| CommandOutput {
-        output: format!(
-            "{}\n{}",
-            String::from_utf8_lossy(&output.stdout),
-            String::from_utf8_lossy(&output.stderr)
-        ),
-        status: output.status,
-    })
+        .envs(env.iter().cloned())
+        .spawn()
+        .map_err(|e| spawn_error(cmd, e))?;
+    feed_stdin(&mut h, input);
+    let output: Output = h.wait_with_output().map_err(HookError::Io)?;
+    let mut combined = output.stdout;
+    combined.push(b'\n');
+    combined.extend(output.stderr);
+    finish(combined, output.status)
 }
 
-pub fn run_command(cmd: &str, args: &Vec<String>) -> io::Result<CommandOutput> {
-    if cfg!(target_os = "linux") {
-        run_command_unix(cmd, args)
+/// Runs the command with additional environment variables, `input` is written to its stdin.
+/// The command and everything it has started are killed after `timeout`.
+/// Only a zero exit status is a success, everything else is a `HookError`.
+pub fn run_command(
+    cmd: &str,
+    args: &Vec<String>,
+    env: &[(String, String)],
+    input: &[u8],
+    timeout: Duration,
+) -> CommandResult {
+    // process groups and the deadline work the same on macOS and the BSDs
+    if cfg!(unix) {
+        run_command_unix(cmd, args, env, input, timeout)
     } else {
         // windows support is currently untested and probably not good
-        run_command_windows(cmd, args)
+        run_command_windows(cmd, args, env, input, timeout)
     }
 }
 
-pub fn run_command_unix(cmd: &str, args: &Vec<String>) -> io::Result<CommandOutput> {
-    let (mut reader, writer) = pipe().unwrap();
-    let writer_clone = writer.try_clone().unwrap();
+pub fn run_command_unix(
+    cmd: &str,
+    args: &Vec<String>,
+    env: &[(String, String)],
+    input: &[u8],
+    timeout: Duration,
+) -> CommandResult {
+    let (mut reader, writer) = pipe().map_err(HookError::SpawnFailed)?;
+    let writer_clone = writer.try_clone().map_err(HookError::SpawnFailed)?;
 
-    let mut cmd = Command::new(cmd);
-    let mut h = cmd
-        .stdin(Stdio::null())
+    let mut command = Command::new(cmd);
+    command
+        .stdin(Stdio::piped())
         .stdout(writer)
         .stderr(writer_clone)
         .args(args)
-        .spawn()
-        .unwrap();
-    drop(cmd);
-    let mut output = String::new();
-    reader.read_to_string(&mut output).unwrap();
-    let rc = h.wait()?;
-    Ok(CommandOutput {
-        output: output,
-        status: rc,
-    })
+        .envs(env.iter().cloned());
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+        // own process group, so the whole tree can be killed on timeout
+        command.process_group(0);
+    }
+    let deadline = Instant::now() + timeout;
+    let spawned = command.spawn();
+    // closes our copies of the write end, otherwise reading never ends
+    drop(command);
+    let mut h = spawned.map_err(|e| spawn_error(cmd, e))?;
+    feed_stdin(&mut h, input);
+    // the output is read until every process that inherited the pipe has exited,
+    // that can take forever, so it's read in a separate thread
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let mut output = Vec::new();
+        let result = reader.read_to_end(&mut output).map(|_| output);
+        let _ = tx.send(result);
+    });
+    let output = match rx.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+        Ok(output) => output,
+        Err(_) => {
+            kill_group(&mut h);
+            let _ = h.wait();
+            return Err(HookError::TimedOut(timeout));
+        }
+    };
+    let output = match output {
+        Ok(output) => output,
+        Err(e) => {
+            kill_group(&mut h);
+            let _ = h.wait();
+            return Err(HookError::Io(e));
+        }
+    };
+    // the output can be closed long before the exit, e.g. `exec >/dev/null 2>&1; sleep 1000`
+    let status = loop {
+        if let Some(status) = h.try_wait().map_err(HookError::Io)? {
+            break status;
+        }
+        let now = Instant::now();
+        if now >= deadline {
+            kill_group(&mut h);
+            let _ = h.wait();
+            return Err(HookError::TimedOut(timeout));
+        }
+        thread::sleep(std::cmp::min(EXIT_POLL_INTERVAL, deadline - now));
+    };
+    finish(output, status)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+
+    const TIMEOUT: Duration = Duration::from_secs(10);
+
     #[test]
     fn should_success() {
         let mut args = Vec::new();
         args.push("1122331".to_string());
-        let output = run_command_unix("tests/run_command.sh", &args);
+        let output = run_command_unix("tests/run_command.sh", &args, &[], b"", TIMEOUT);
         assert!(output.unwrap().status.success());
     }
 
@@ -78,7 +277,7 @@ mod tests {
     fn should_combine_stderr() {
         let mut args = Vec::new();
         args.push("1122331".to_string());
-        let output = run_command_unix("tests/run_command.sh", &args);
+        let output = run_command_unix("tests/run_command.sh", &args, &[], b"", TIMEOUT);
         assert!(output.unwrap().output.contains("should capture stderr as well"));
     }
 
@@ -86,9 +285,105 @@ mod tests {
     fn should_fail_err_1() {
         let mut args = Vec::new();
         args.push("some-id-error".to_string());
-        let output = run_command_unix("tests/run_command.sh", &args);
-        let status = output.unwrap().status;
-        assert!(!status.success());
-        assert_eq!(status.code(), Some(1));
+        let output = run_command_unix("tests/run_command.sh", &args, &[], b"", TIMEOUT);
+        match output.unwrap_err() {
+            HookError::ExitCode { code, output } => {
+                assert_eq!(code, 1);
+                assert!(output.contains("Finished with error"));
+            }
+            e => panic!("unexpected error: {:?}", e),
+        }
+    }
+
+    #[test]
+    fn should_pass_env_and_stdin() {
+        let args = vec!["-c".to_string(), "echo \"$DOCKER_CHECK_EVENT\"; cat".to_string()];
+        let env = vec![("DOCKER_CHECK_EVENT".to_string(), "restart".to_string())];
+        let output = run_command_unix("/bin/sh", &args, &env, b"{\"event\": \"restart\"}", TIMEOUT).unwrap();
+        assert!(output.status.success());
+        assert_eq!(output.output, "restart\n{\"event\": \"restart\"}");
+    }
+
+    #[test]
+    fn should_ignore_unread_stdin() {
+        let mut args = Vec::new();
+        args.push("1122331".to_string());
+        let input = vec![b'x'; 1 << 20];
+        let output = run_command_unix("tests/run_command.sh", &args, &[], &input, TIMEOUT);
+        assert!(output.unwrap().status.success());
+    }
+
+    #[test]
+    fn should_kill_process_group_on_timeout() {
+        // the background sleep keeps the output pipe open after the shell is killed
+        let args = vec!["-c".to_string(), "sleep 30 & sleep 30".to_string()];
+        let started = Instant::now();
+        let error = run_command_unix("/bin/sh", &args, &[], b"", Duration::from_millis(200)).unwrap_err();
+        assert!(error.is_retryable());
+        match error {
+            HookError::TimedOut(timeout) => assert_eq!(timeout, Duration::from_millis(200)),
+            e => panic!("unexpected error: {:?}", e),
+        }
+        assert!(started.elapsed() < Duration::from_secs(10));
+    }
+
+    #[test]
+    fn should_kill_process_that_closed_its_output_on_timeout() {
+        let args = vec!["-c".to_string(), "exec >/dev/null 2>&1; sleep 30".to_string()];
+        let started = Instant::now();
+        match run_command_unix("/bin/sh", &args, &[], b"", Duration::from_millis(200)).unwrap_err() {
+            HookError::TimedOut(timeout) => assert_eq!(timeout, Duration::from_millis(200)),
+            e => panic!("unexpected error: {:?}", e),
+        }
+        assert!(started.elapsed() < Duration::from_secs(10));
+    }
+
+    #[test]
+    fn should_fail_to_spawn_missing_command() {
+        match run_command_unix("tests/missing-hook.sh", &Vec::new(), &[], b"", TIMEOUT).unwrap_err() {
+            HookError::SpawnFailed(e) => assert_eq!(e.kind(), io::ErrorKind::NotFound),
+            e => panic!("unexpected error: {:?}", e),
+        }
+    }
+
+    #[test]
+    fn should_report_not_executable() {
+        match run_command_unix("tests/settings.toml", &Vec::new(), &[], b"", TIMEOUT).unwrap_err() {
+            HookError::NotExecutable(cmd) => assert_eq!(cmd, "tests/settings.toml"),
+            e => panic!("unexpected error: {:?}", e),
+        }
+    }
+
+    #[test]
+    fn should_report_non_utf8_output() {
+        let error = run_command_unix("tests/non_utf8_output.sh", &Vec::new(), &[], b"", TIMEOUT).unwrap_err();
+        assert_eq!(error.status(), "error");
+        assert!(!error.is_retryable());
+        match error {
+            HookError::NonUtf8 { .. } => {}
+            e => panic!("unexpected error: {:?}", e),
+        }
+        // a failed exit status wins
+        let args = vec!["3".to_string()];
+        let error = run_command_unix("tests/non_utf8_output.sh", &args, &[], b"", TIMEOUT).unwrap_err();
+        assert_eq!(error.output(), "caf\u{FFFD}\n");
+        assert_eq!(error.status(), "3");
+        match error {
+            HookError::ExitCode { code: 3, .. } => {}
+            e => panic!("unexpected error: {:?}", e),
+        }
+    }
+
+    #[test]
+    fn should_report_signal() {
+        let error = run_command_unix("tests/killed_by_signal.sh", &Vec::new(), &[], b"", TIMEOUT).unwrap_err();
+        assert_eq!(error.status(), "signal");
+        match error {
+            HookError::Signal { signal, output } => {
+                assert_eq!(signal, libc::SIGTERM);
+                assert_eq!(output, "about to be killed\n");
+            }
+            e => panic!("unexpected error: {:?}", e),
+        }
     }
 }
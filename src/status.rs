@@ -0,0 +1,183 @@
+/* Status API of the checker:
+    /healthz      - liveness of the check loop itself
+    /containers   - state and stats of every watched container
+    /node-health  - 503 when any critical container is unhealthy or has reached hard_failures
+                    and hasn't recovered since then, meant to be used as a load balancer health check of the node
+*/
+use docker_checker::Health;
+use metrics::{ContainerSnapshot, Metrics, METRICS};
+use serde_json;
+use std::thread;
+use std::time::Duration;
+use tiny_http::{Header, Response, Server};
+
+#[derive(Serialize)]
+struct ContainerStatus<'a> {
+    id: &'a str,
+    name: &'a str,
+    image: &'a str,
+    service: &'a str,
+    health: &'a str,
+    critical: bool,
+    count: u32,
+    restarts: u32,
+    consecutive_failures: u16,
+    hard_failures: u16,
+    hard_failed: bool,
+    last_restart: Option<String>,
+}
+
+impl<'a> From<&'a ContainerSnapshot> for ContainerStatus<'a> {
+    fn from(c: &'a ContainerSnapshot) -> Self {
+        Self {
+            id: &c.id,
+            name: &c.name,
+            image: &c.image,
+            service: &c.service,
+            health: health_str(c.health),
+            critical: c.critical,
+            count: c.count,
+            restarts: c.restarts,
+            consecutive_failures: c.consecutive_failures,
+            hard_failures: c.hard_failures,
+            hard_failed: c.hard_failed,
+            last_restart: c.last_restart.map(|time| time.to_rfc3339()),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct NodeHealth<'a> {
+    healthy: bool,
+    // names of the critical containers that make the node unhealthy
+    failing: Vec<&'a str>,
+}
+
+fn health_str(health: Option<Health>) -> &'static str {
+    match health {
+        Some(Health::Healthy) => "healthy",
+        Some(Health::Unhealthy) => "unhealthy",
+        Some(Health::Starting) => "starting",
+        None => "unknown",
+    }
+}
+
+pub(crate) fn is_failing(c: &ContainerSnapshot) -> bool {
+    c.critical && (c.health == Some(Health::Unhealthy) || c.hard_failed)
+}
+
+const TEXT: &str = "text/plain";
+const JSON: &str = "application/json";
+
+/// Returns status code, content type and body for the request path
+pub(crate) fn handle(metrics: &Metrics, url: &str, stale_after: Duration) -> (u16, &'static str, String) {
+    let path = url.split('?').next().unwrap_or("");
+    match path {
+        "/healthz" => match metrics.last_loop() {
+            Some(last) if last.elapsed() <= stale_after => (200, TEXT, "ok".to_string()),
+            Some(last) => (
+                503,
+                TEXT,
+                format!("last check loop was {}s ago", last.elapsed().as_secs()),
+            ),
+            None => (503, TEXT, "no check loop has finished yet".to_string()),
+        },
+        "/containers" => {
+            let containers = metrics.containers();
+            let statuses = containers.iter().map(ContainerStatus::from).collect::<Vec<_>>();
+            (200, JSON, serde_json::to_string(&statuses).unwrap_or_default())
+        }
+        "/node-health" => {
+            let containers = metrics.containers();
+            let failing = containers
+                .iter()
+                .filter(|c| is_failing(c))
+                .map(|c| c.name.as_str())
+                .collect::<Vec<_>>();
+            let health = NodeHealth {
+                healthy: failing.is_empty(),
+                failing,
+            };
+            let status = if health.healthy { 200 } else { 503 };
+            (status, JSON, serde_json::to_string(&health).unwrap_or_default())
+        }
+        _ => (404, TEXT, "Not found".to_string()),
+    }
+}
+
+/// Serves the status API in a separate thread.
+/// `/healthz` fails when no check loop has finished for `stale_after`.
+pub fn serve(listen: &str, stale_after: Duration) -> Result<(), String> {
+    let server = Server::http(listen).map_err(|e| format!("Cannot listen on {}: {}", listen, e))?;
+    info!("Serving status API on http://{}", listen);
+    thread::spawn(move || {
+        for request in server.incoming_requests() {
+            let (status, content_type, body) = handle(&METRICS, request.url(), stale_after);
+            let content_type =
+                Header::from_bytes(&b"Content-Type"[..], content_type.as_bytes()).expect("static header is valid");
+            let response = Response::from_string(body)
+                .with_header(content_type)
+                .with_status_code(status);
+            if let Err(e) = request.respond(response) {
+                debug!("Cannot send status response: {}", e);
+            }
+        }
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot(name: &str, health: Health, restarts: u32, critical: bool) -> ContainerSnapshot {
+        ContainerSnapshot {
+            id: format!("{}-id", name),
+            name: name.to_string(),
+            health: Some(health),
+            restarts,
+            hard_failures: 3,
+            hard_failed: restarts >= 3,
+            critical,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn is_failing_test() {
+        assert!(!is_failing(&snapshot("web", Health::Unhealthy, 0, false)));
+        assert!(is_failing(&snapshot("db", Health::Unhealthy, 0, true)));
+        assert!(is_failing(&snapshot("db", Health::Healthy, 3, true)));
+        assert!(!is_failing(&snapshot("db", Health::Healthy, 2, true)));
+        assert!(!is_failing(&snapshot("db", Health::Starting, 0, true)));
+        // healthy again after the hard failure, restarts are never reset
+        let mut recovered = snapshot("db", Health::Healthy, 3, true);
+        recovered.hard_failed = false;
+        assert!(!is_failing(&recovered));
+    }
+
+    #[test]
+    fn handle_test() {
+        let metrics = Metrics::default();
+        metrics.set_containers(vec![
+            snapshot("/web", Health::Unhealthy, 5, false),
+            snapshot("/db", Health::Unhealthy, 0, true),
+        ]);
+        let (status, content_type, body) = handle(&metrics, "/node-health", Duration::from_secs(60));
+        assert_eq!(status, 503);
+        assert_eq!(content_type, JSON);
+        assert_eq!(body, r#"{"healthy":false,"failing":["/db"]}"#);
+
+        let (status, _, body) = handle(&metrics, "/containers?pretty", Duration::from_secs(60));
+        assert_eq!(status, 200);
+        let containers: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(containers[1]["name"], "/db");
+        assert_eq!(containers[1]["health"], "unhealthy");
+        assert_eq!(containers[1]["last_restart"], serde_json::Value::Null);
+
+        assert_eq!(handle(&metrics, "/healthz", Duration::from_secs(60)).0, 503);
+        metrics.observe_loop(Duration::from_millis(10));
+        assert_eq!(handle(&metrics, "/healthz", Duration::from_secs(60)).0, 200);
+        assert_eq!(handle(&metrics, "/nope", Duration::from_secs(60)).0, 404);
+    }
+}
@@ -0,0 +1,683 @@
+/* Offline validation of the config file for `docker-check validate`.
+    Unlike `config::get_settings` it doesn't stop on the first error, but collects every problem
+    together with the key it was found at.
+*/
+use config::{self, ApplyTo, Config, TlsFiles};
+use hooks;
+use log::LevelFilter;
+use notifiers::EventKind;
+use regex::Regex;
+use serde_json::Value;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+// Every key that the config understands. `*` matches any key of a map, `[]` any element of an array
+const KNOWN_KEYS: &[&str] = &[
+    "logging.checker",
+    "logging.default",
+    "docker.connect_uri",
+    "docker.tls",
+    "docker.cert_path",
+    "docker.tls_ca",
+    "docker.tls_cert",
+    "docker.tls_key",
+    "docker.purge_unseen",
+    "docker.use_events",
+    "docker.reconcile_interval",
+    "docker.stop_timeout",
+    "containers.filter_by",
+    "containers.filter_self",
+    "containers.apply_filter_to",
+    "containers.consecutive_failures",
+    "containers.hard_failures",
+    "containers.run_on_failure",
+    "containers.poll_interval",
+    "containers.dry_run",
+    "containers.critical",
+    "containers.label_filters.*",
+    "containers.hooks.on_unhealthy",
+    "containers.hooks.on_restart",
+    "containers.hooks.on_recovered",
+    "containers.hooks.on_hard_failure",
+    "containers.hooks.on_disappeared",
+    "containers.hooks.timeout",
+    "containers.policy[].name",
+    "containers.policy[].filter_by",
+    "containers.policy[].filter_self",
+    "containers.policy[].apply_filter_to",
+    "containers.policy[].consecutive_failures",
+    "containers.policy[].hard_failures",
+    "containers.policy[].run_on_failure",
+    "containers.policy[].stop_timeout",
+    "containers.policy[].critical",
+    "containers.policy[].label_filters.*",
+    "containers.policy[].hooks.on_unhealthy",
+    "containers.policy[].hooks.on_restart",
+    "containers.policy[].hooks.on_recovered",
+    "containers.policy[].hooks.on_hard_failure",
+    "containers.policy[].hooks.on_disappeared",
+    "containers.policy[].hooks.timeout",
+    "aws.enabled",
+    "aws.region",
+    "aws.imds_url",
+    "aws.timeout",
+    "aws.asg.healthcheck",
+    "aws.asg.endpoint",
+    "aws.asg.respect_grace_period",
+    "aws.cloudwatch.enabled",
+    "aws.cloudwatch.namespace",
+    "aws.cloudwatch.interval",
+    "aws.cloudwatch.dimensions",
+    "aws.cloudwatch.asg_name",
+    "aws.cloudwatch.endpoint",
+    "hooks.timeout",
+    "hooks.max_concurrent",
+    "hooks.queue_size",
+    "hooks.retries",
+    "hooks.backoff",
+    "hooks.jitter",
+    "hooks.log_dir",
+    "hooks.max_log_size",
+    "hooks.keep_logs",
+    "dead_letters.path",
+    "reload.watch_file",
+    "metrics.enabled",
+    "metrics.listen",
+    "metrics.textfile_dir",
+    "status.enabled",
+    "status.listen",
+    "notifiers[].name",
+    "notifiers[].type",
+    "notifiers[].url",
+    "notifiers[].routing_key",
+    "notifiers[].smtp.host",
+    "notifiers[].smtp.port",
+    "notifiers[].smtp.starttls",
+    "notifiers[].smtp.username",
+    "notifiers[].smtp.password",
+    "notifiers[].smtp.from",
+    "notifiers[].smtp.to",
+    "notifiers[].headers.*",
+    "notifiers[].format",
+    "notifiers[].timeout",
+    "notifiers[].retries",
+    "notifiers[].backoff",
+    "notifiers[].jitter",
+    "notifiers[].events",
+];
+
+const CONNECT_SCHEMES: [&str; 4] = ["unix://", "http://", "tcp://", "https://"];
+const NOTIFIER_TYPES: [&str; 4] = ["webhook", "pagerduty", "alertmanager", "email"];
+const MESSAGE_FORMATS: [&str; 4] = ["json", "slack", "teams", "discord"];
+const CLOUDWATCH_DIMENSIONS: [&str; 3] = ["instance_id", "asg_name", "container_name"];
+
+#[derive(Debug, PartialEq)]
+pub struct Problem {
+    pub key: String,
+    pub message: String,
+}
+
+impl Problem {
+    fn new<K: Into<String>, M: Into<String>>(key: K, message: M) -> Self {
+        Self {
+            key: key.into(),
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.key.is_empty() {
+            write!(f, "{}", self.message)
+        } else {
+            write!(f, "{}: {}", self.key, self.message)
+        }
+    }
+}
+
+/// Validates the config file with the environment on top of it, the way `config::get_settings` loads it.
+/// Returns every problem found. Empty result means the config is valid.
+pub fn validate(filename: &str) -> Vec<Problem> {
+    validate_with(filename, config::environment())
+}
+
+fn validate_with<S>(filename: &str, overlay: S) -> Vec<Problem>
+where
+    S: 'static + configuration::Source + Send + Sync,
+{
+    let mut settings = configuration::Config::default();
+    if let Err(e) = settings.merge(configuration::File::with_name(filename)) {
+        return vec![Problem::new("", format!("Cannot read config: {}", e))];
+    }
+    let file = match settings.clone().try_into::<Value>() {
+        Ok(file) => file,
+        Err(e) => return vec![Problem::new("", format!("Cannot read config: {}", e))],
+    };
+    if let Err(e) = settings.merge(overlay) {
+        return vec![Problem::new("", format!("Cannot read config: {}", e))];
+    }
+    let raw = match settings.clone().try_into::<Value>() {
+        Ok(raw) => raw,
+        Err(e) => return vec![Problem::new("", format!("Cannot read config: {}", e))],
+    };
+
+    let mut problems = Vec::new();
+    // only the file, any APP_* variable of the environment is picked up and most of them aren't settings
+    check_unknown_keys(&file, "", "", &mut problems);
+    check_values(&raw, &mut problems);
+
+    // catches missing keys and wrong types, which are most likely caused by what was already reported otherwise
+    match settings.try_into::<Config>() {
+        Ok(config) => {
+            // the endpoint of a docker context is only known at startup, so only connect_uri is checked
+            if let Some(ref uri) = config.docker.connect_uri {
+                check_tls(
+                    uri,
+                    config.docker.tls_enabled(),
+                    &config.docker.tls_files(),
+                    &mut problems,
+                );
+            }
+        }
+        Err(e) => {
+            if problems.is_empty() {
+                problems.push(Problem::new("", format!("Cannot parse config: {}", e)));
+            }
+        }
+    }
+    problems
+}
+
+/// The docker client connects over TLS only with a client identity, server-only TLS isn't supported.
+fn check_tls(uri: &str, tls: bool, files: &TlsFiles, problems: &mut Vec<Problem>) {
+    let uses_tls = uri.starts_with("https://") || (tls && uri.starts_with("tcp://"));
+    if uses_tls && files.cert.is_none() && files.key.is_none() {
+        problems.push(Problem::new(
+            "docker.tls_cert",
+            "TLS requires a client certificate and key (server-only TLS isn't supported), \
+             set docker.tls_cert and docker.tls_key or put cert.pem and key.pem into docker.cert_path",
+        ));
+    }
+}
+
+fn join(parent: &str, key: &str) -> String {
+    if parent.is_empty() {
+        key.to_string()
+    } else {
+        format!("{}.{}", parent, key)
+    }
+}
+
+fn is_known(path: &str) -> bool {
+    KNOWN_KEYS.iter().any(|known| *known == path)
+}
+
+fn is_section(path: &str) -> bool {
+    KNOWN_KEYS.iter().any(|known| {
+        known.starts_with(path) && {
+            let rest = &known[path.len()..];
+            rest.starts_with('.') || rest.starts_with("[]")
+        }
+    })
+}
+
+/// `path` is the one reported to the user (with array indices), `pattern` is the one matched against KNOWN_KEYS
+fn check_unknown_keys(value: &Value, path: &str, pattern: &str, problems: &mut Vec<Problem>) {
+    match value {
+        Value::Object(map) => {
+            if is_known(&join(pattern, "*")) {
+                return;
+            }
+            for (key, child) in map.iter() {
+                let child_path = join(path, key);
+                let child_pattern = join(pattern, key);
+                if is_known(&child_pattern) {
+                    continue;
+                }
+                if is_section(&child_pattern) {
+                    check_unknown_keys(child, &child_path, &child_pattern, problems);
+                } else {
+                    problems.push(Problem::new(child_path, "unknown key"));
+                }
+            }
+        }
+        Value::Array(items) => {
+            for (idx, item) in items.iter().enumerate() {
+                check_unknown_keys(item, &format!("{}[{}]", path, idx), &format!("{}[]", pattern), problems);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn check_values(raw: &Value, problems: &mut Vec<Problem>) {
+    for key in ["checker", "default"].iter() {
+        if let Some(level) = raw.pointer(&format!("/logging/{}", key)).and_then(Value::as_str) {
+            if LevelFilter::from_str(level).is_err() {
+                problems.push(Problem::new(
+                    format!("logging.{}", key),
+                    format!("unknown log level \"{}\"", level),
+                ));
+            }
+        }
+    }
+
+    if let Some(uri) = raw.pointer("/docker/connect_uri").and_then(Value::as_str) {
+        if !CONNECT_SCHEMES.iter().any(|scheme| uri.starts_with(scheme)) {
+            problems.push(Problem::new(
+                "docker.connect_uri",
+                format!(
+                    "unsupported URI \"{}\", expected one of: {}",
+                    uri,
+                    CONNECT_SCHEMES.join(", ")
+                ),
+            ));
+        }
+    }
+    // the client certificate is useless without its key
+    match (raw.pointer("/docker/tls_cert"), raw.pointer("/docker/tls_key")) {
+        (Some(_), None) => problems.push(Problem::new(
+            "docker.tls_key",
+            "must be set together with docker.tls_cert",
+        )),
+        (None, Some(_)) => problems.push(Problem::new(
+            "docker.tls_cert",
+            "must be set together with docker.tls_key",
+        )),
+        _ => {}
+    }
+    check_duration(raw.pointer("/docker/purge_unseen"), "docker.purge_unseen", problems);
+    check_duration(raw.pointer("/docker/stop_timeout"), "docker.stop_timeout", problems);
+    check_duration(raw.pointer("/aws/timeout"), "aws.timeout", problems);
+    check_duration(
+        raw.pointer("/aws/cloudwatch/interval"),
+        "aws.cloudwatch.interval",
+        problems,
+    );
+    if let Some(dimensions) = raw.pointer("/aws/cloudwatch/dimensions").and_then(Value::as_array) {
+        for (idx, dimension) in dimensions.iter().enumerate() {
+            let dimension = dimension.as_str().unwrap_or_default();
+            if !CLOUDWATCH_DIMENSIONS.contains(&dimension) {
+                problems.push(Problem::new(
+                    format!("aws.cloudwatch.dimensions[{}]", idx),
+                    format!(
+                        "unknown dimension \"{}\", expected one of: {}",
+                        dimension,
+                        CLOUDWATCH_DIMENSIONS.join(", ")
+                    ),
+                ));
+            }
+        }
+    }
+    if let Some(dir) = raw.pointer("/metrics/textfile_dir").and_then(Value::as_str) {
+        if !Path::new(dir).is_dir() {
+            problems.push(Problem::new(
+                "metrics.textfile_dir",
+                format!("\"{}\" is not a directory", dir),
+            ));
+        }
+    }
+    check_duration(
+        raw.pointer("/docker/reconcile_interval"),
+        "docker.reconcile_interval",
+        problems,
+    );
+    check_duration(raw.pointer("/hooks/timeout"), "hooks.timeout", problems);
+    check_positive(raw.pointer("/hooks/max_concurrent"), "hooks.max_concurrent", problems);
+    check_positive(raw.pointer("/hooks/queue_size"), "hooks.queue_size", problems);
+    check_duration(raw.pointer("/hooks/backoff"), "hooks.backoff", problems);
+    check_jitter(raw.pointer("/hooks/jitter"), "hooks.jitter", problems);
+    for key in ["max_log_size", "keep_logs"].iter() {
+        check_positive(
+            raw.pointer(&format!("/hooks/{}", key)),
+            &format!("hooks.{}", key),
+            problems,
+        );
+    }
+    if let Some(dir) = raw.pointer("/hooks/log_dir").and_then(Value::as_str) {
+        // it's created when missing
+        if Path::new(dir).exists() && !Path::new(dir).is_dir() {
+            problems.push(Problem::new("hooks.log_dir", format!("\"{}\" is not a directory", dir)));
+        }
+    }
+    if let Some(path) = raw.pointer("/dead_letters/path").and_then(Value::as_str) {
+        let dir = Path::new(path).parent().filter(|dir| !dir.as_os_str().is_empty());
+        if !dir.map_or(true, Path::is_dir) {
+            problems.push(Problem::new(
+                "dead_letters.path",
+                format!("directory of \"{}\" doesn't exist", path),
+            ));
+        }
+    }
+
+    if let Some(containers) = raw.get("containers") {
+        check_containers_section(containers, "containers", true, problems);
+        check_duration(containers.get("poll_interval"), "containers.poll_interval", problems);
+        if let Some(groups) = containers.get("policy").and_then(Value::as_array) {
+            for (idx, group) in groups.iter().enumerate() {
+                check_containers_section(group, &format!("containers.policy[{}]", idx), false, problems);
+                check_duration(
+                    group.get("stop_timeout"),
+                    &format!("containers.policy[{}].stop_timeout", idx),
+                    problems,
+                );
+            }
+        }
+    }
+
+    if let Some(notifiers) = raw.get("notifiers").and_then(Value::as_array) {
+        for (idx, notifier) in notifiers.iter().enumerate() {
+            check_notifier(notifier, &format!("notifiers[{}]", idx), problems);
+        }
+    }
+}
+
+fn check_notifier(notifier: &Value, path: &str, problems: &mut Vec<Problem>) {
+    let kind = notifier.get("type").and_then(Value::as_str);
+    match kind {
+        Some(kind) if !NOTIFIER_TYPES.contains(&kind) => problems.push(Problem::new(
+            join(path, "type"),
+            format!(
+                "unknown notifier type \"{}\", expected one of: {}",
+                kind,
+                NOTIFIER_TYPES.join(", ")
+            ),
+        )),
+        Some(_) => {}
+        None => problems.push(Problem::new(join(path, "type"), "is missing")),
+    }
+    let required = match kind {
+        Some("webhook") | Some("alertmanager") => Some("url"),
+        Some("pagerduty") => Some("routing_key"),
+        Some("email") => Some("smtp"),
+        _ => None,
+    };
+    if let Some(key) = required {
+        if notifier.get(key).is_none() {
+            problems.push(Problem::new(join(path, key), "is missing"));
+        }
+    }
+    if let Some(format) = notifier.get("format").and_then(Value::as_str) {
+        if !MESSAGE_FORMATS.contains(&format) {
+            problems.push(Problem::new(
+                join(path, "format"),
+                format!(
+                    "unknown format \"{}\", expected one of: {}",
+                    format,
+                    MESSAGE_FORMATS.join(", ")
+                ),
+            ));
+        }
+    }
+    for key in ["timeout", "backoff"].iter() {
+        check_duration(notifier.get(*key), &join(path, key), problems);
+    }
+    check_jitter(notifier.get("jitter"), &join(path, "jitter"), problems);
+    if let Some(events) = notifier.get("events").and_then(Value::as_array) {
+        for (idx, event) in events.iter().enumerate() {
+            let event = event.as_str().unwrap_or_default();
+            if !EventKind::ALL.iter().any(|kind| kind.as_str() == event) {
+                problems.push(Problem::new(
+                    format!("{}.events[{}]", path, idx),
+                    format!(
+                        "unknown event \"{}\", expected one of: {}",
+                        event,
+                        EventKind::ALL
+                            .iter()
+                            .map(|kind| kind.as_str())
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    ),
+                ));
+            }
+        }
+    }
+    if let Some(smtp) = notifier.get("smtp") {
+        for key in ["host", "from"].iter() {
+            if smtp.get(*key).is_none() {
+                problems.push(Problem::new(format!("{}.smtp.{}", path, key), "is missing"));
+            }
+        }
+        let recipients = smtp.get("to").and_then(Value::as_array).map_or(0, Vec::len);
+        if recipients == 0 {
+            problems.push(Problem::new(
+                format!("{}.smtp.to", path),
+                "must contain at least one recipient",
+            ));
+        }
+        let credentials = smtp.get("username").is_some() || smtp.get("password").is_some();
+        if credentials && smtp.get("starttls").and_then(Value::as_bool) == Some(false) {
+            problems.push(Problem::new(
+                format!("{}.smtp.starttls", path),
+                "must be true when username or password is set, they would be sent in plain text",
+            ));
+        }
+    }
+}
+
+/// Checks shared between `[containers]` and `[[containers.policy]]`
+fn check_containers_section(section: &Value, path: &str, hook_required: bool, problems: &mut Vec<Problem>) {
+    for key in ["filter_by", "filter_self"].iter() {
+        if let Some(pattern) = section.get(*key).and_then(Value::as_str) {
+            check_regex(pattern, &join(path, key), problems);
+        }
+    }
+    if let Some(filters) = section.get("label_filters").and_then(Value::as_object) {
+        for (label, pattern) in filters.iter() {
+            if let Some(pattern) = pattern.as_str() {
+                check_regex(pattern, &format!("{}.label_filters.\"{}\"", path, label), problems);
+            }
+        }
+    }
+    if let Some(values) = section.get("apply_filter_to").and_then(Value::as_array) {
+        let values = values
+            .iter()
+            .map(|v| v.as_str().unwrap_or_default().to_string())
+            .collect::<Vec<_>>();
+        if let Err(e) = ApplyTo::from_vec(&values) {
+            problems.push(Problem::new(join(path, "apply_filter_to"), e));
+        }
+    }
+    for key in ["consecutive_failures", "hard_failures"].iter() {
+        check_positive(section.get(*key), &join(path, key), problems);
+    }
+    match section.get("run_on_failure").and_then(Value::as_str) {
+        Some(hook) => {
+            if let Err(e) = check_hook(hook) {
+                problems.push(Problem::new(join(path, "run_on_failure"), e));
+            }
+        }
+        None if hook_required => problems.push(Problem::new(join(path, "run_on_failure"), "is missing")),
+        None => {}
+    }
+    for kind in EventKind::ALL.iter() {
+        let key = format!("on_{}", kind.as_str());
+        if let Some(hook) = section.pointer(&format!("/hooks/{}", key)).and_then(Value::as_str) {
+            if let Err(e) = check_hook(hook) {
+                problems.push(Problem::new(format!("{}.hooks.{}", path, key), e));
+            }
+        }
+    }
+    check_duration(
+        section.pointer("/hooks/timeout"),
+        &format!("{}.hooks.timeout", path),
+        problems,
+    );
+}
+
+fn check_regex(pattern: &str, path: &str, problems: &mut Vec<Problem>) {
+    if let Err(e) = Regex::new(pattern) {
+        problems.push(Problem::new(path, format!("invalid regex: {}", e)));
+    }
+}
+
+fn check_positive(value: Option<&Value>, path: &str, problems: &mut Vec<Problem>) {
+    if let Some(number) = value.and_then(Value::as_i64) {
+        if number <= 0 {
+            problems.push(Problem::new(path, format!("must be greater than 0, got {}", number)));
+        }
+    }
+}
+
+fn check_jitter(value: Option<&Value>, path: &str, problems: &mut Vec<Problem>) {
+    if let Some(jitter) = value.and_then(Value::as_f64) {
+        if !(0.0..=1.0).contains(&jitter) {
+            problems.push(Problem::new(path, format!("must be between 0 and 1, got {}", jitter)));
+        }
+    }
+}
+
+fn check_duration(value: Option<&Value>, path: &str, problems: &mut Vec<Problem>) {
+    if let Some(value) = value.and_then(Value::as_str) {
+        match config::parse_duration(value) {
+            Ok(duration) if duration.as_secs() == 0 && duration.subsec_nanos() == 0 => {
+                problems.push(Problem::new(path, "must be greater than 0"))
+            }
+            Ok(_) => {}
+            Err(e) => problems.push(Problem::new(path, e)),
+        }
+    }
+}
+
+/// Hook should be a valid template of an existing executable file.
+/// A bare command name is looked up in PATH, like the hook is spawned.
+pub(crate) fn check_hook(template: &str) -> Result<(), String> {
+    let words = hooks::parse(template)?;
+    let path = words[0].as_str();
+    if Path::new(path).components().count() > 1 {
+        return check_executable(Path::new(path));
+    }
+    env::var_os("PATH")
+        .iter()
+        .flat_map(env::split_paths)
+        .map(|dir| dir.join(path))
+        .find(|candidate| check_executable(candidate).is_ok())
+        .map(|_| ())
+        .ok_or_else(|| format!("hook \"{}\" is not found in PATH", path))
+}
+
+fn check_executable(path: &Path) -> Result<(), String> {
+    let metadata = fs::metadata(path).map_err(|e| format!("hook \"{}\" cannot be accessed: {}", path.display(), e))?;
+    if !metadata.is_file() {
+        return Err(format!("hook \"{}\" is not a file", path.display()));
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        if metadata.permissions().mode() & 0o111 == 0 {
+            return Err(format!("hook \"{}\" is not executable", path.display()));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    #[test]
+    fn valid_config() {
+        assert_eq!(validate("tests/settings"), Vec::new());
+    }
+
+    #[test]
+    fn environment_is_validated() {
+        let overlay = configuration::File::from_str("[hooks]\nmax_concurrent = 0\n", configuration::FileFormat::Toml);
+        let problems = validate_with("tests/settings", overlay);
+        assert_eq!(problems.len(), 1, "{:?}", problems);
+        assert_eq!(problems[0].key, "hooks.max_concurrent");
+    }
+
+    #[test]
+    fn missing_config() {
+        let problems = validate("tests/no-such-settings");
+        assert_eq!(problems.len(), 1);
+        assert!(problems[0].message.starts_with("Cannot read config"));
+    }
+
+    #[test]
+    fn every_problem_is_reported() {
+        let problems = validate("tests/invalid_settings");
+        let keys = problems.iter().map(|p| p.key.as_str()).collect::<Vec<_>>();
+        for key in [
+            "logging.default",
+            "docker.connect_uri",
+            "docker.colour",
+            "docker.tls_key",
+            "docker.stop_timeout",
+            "containers.filter_by",
+            "containers.filter_self",
+            "containers.apply_filter_to",
+            "containers.consecutive_failures",
+            "containers.run_on_failure",
+            "containers.label_filters.\"im.lain.docker-check\"",
+            "containers.hooks.on_restart",
+            "hooks.max_concurrent",
+            "hooks.queue_size",
+            "hooks.jitter",
+            "hooks.log_dir",
+            "hooks.keep_logs",
+            "dead_letters.path",
+            "notifiers[0].smtp.starttls",
+            "containers.policy[0].hard_failures",
+            "containers.policy[0].run_on_failure",
+            "containers.policy[0].threshold",
+        ]
+        .iter()
+        {
+            assert!(keys.contains(key), "{} should be reported, got {:?}", key, problems);
+        }
+        assert_eq!(problems.len(), 22, "{:?}", problems);
+    }
+
+    #[test]
+    fn check_tls_test() {
+        let ca_only = TlsFiles {
+            ca: PathBuf::from("/etc/docker/certs/ca.pem"),
+            cert: None,
+            key: None,
+        };
+        let mut problems = Vec::new();
+        check_tls("tcp://docker.local:2376", true, &ca_only, &mut problems);
+        check_tls("https://docker.local:2376", false, &ca_only, &mut problems);
+        assert_eq!(problems.len(), 2, "{:?}", problems);
+        assert!(problems.iter().all(|p| p.key == "docker.tls_cert"));
+
+        problems.clear();
+        check_tls("tcp://docker.local:2375", false, &ca_only, &mut problems);
+        check_tls("unix:///var/run/docker.sock", true, &ca_only, &mut problems);
+        let client = TlsFiles {
+            cert: Some(PathBuf::from("/etc/docker/certs/cert.pem")),
+            key: Some(PathBuf::from("/etc/docker/certs/key.pem")),
+            ..ca_only
+        };
+        check_tls("tcp://docker.local:2376", true, &client, &mut problems);
+        assert_eq!(problems, Vec::new());
+    }
+
+    #[test]
+    fn check_hook_test() {
+        assert!(check_hook("tests/run_command.sh").is_ok());
+        assert!(check_hook("").unwrap_err().contains("empty"));
+        assert!(check_hook("tests/settings.toml")
+            .unwrap_err()
+            .contains("not executable"));
+        assert!(check_hook("tests").unwrap_err().contains("not a file"));
+        assert!(check_hook("tests/run_command.sh --name={name} {event}").is_ok());
+        assert!(check_hook("tests/run_command.sh {nmae}")
+            .unwrap_err()
+            .contains("unknown placeholder"));
+        assert!(check_hook("tests/missing-hook.sh")
+            .unwrap_err()
+            .contains("cannot be accessed"));
+        assert!(check_hook("sh -c true").is_ok());
+        assert!(check_hook("no-such-docker-check-hook")
+            .unwrap_err()
+            .contains("not found in PATH"));
+    }
+}
@@ -0,0 +1,5 @@
+#!/bin/bash
+
+# Latin-1 output, e.g. from a misconfigured locale
+printf "caf\xe9\n"
+exit ${1:-0}